@@ -0,0 +1,28 @@
+//! Compares borrowing a thread local with a token against `LocalKey::with`.
+//!
+//! Run it with `cargo bench` and compare the reported times of both forms.
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use stack_tokens::{stack_token, LocalKeyExt};
+
+thread_local! {
+    static COUNTER: AtomicUsize = const { AtomicUsize::new(0) };
+}
+
+fn bench_borrow(c: &mut Criterion) {
+    let mut group = c.benchmark_group("fetch_add");
+    group.bench_function("with", |b| {
+        b.iter(|| COUNTER.with(|counter| black_box(counter.fetch_add(1, Ordering::Relaxed))))
+    });
+
+    stack_token!(scope);
+    group.bench_function("borrow", |b| {
+        b.iter(|| black_box(COUNTER.borrow(scope).fetch_add(1, Ordering::Relaxed)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_borrow);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+use core::ptr::NonNull;
+
+use generativity::Guard;
+
+use crate::StackToken;
+
+impl<'id> StackToken<'id> {
+    /// Creates a token that shares its brand with a `generativity` guard.
+    ///
+    /// This lets code that already brands its data with
+    /// `generativity::make_guard!` use the same `'id` for stack tokens, so
+    /// the compiler can check that both belong together.  The token is
+    /// borrowed from the guard and cannot outlive it.
+    ///
+    /// ```
+    /// use generativity::make_guard;
+    /// use stack_tokens::{LocalKeyExt, StackToken};
+    ///
+    /// thread_local! {
+    ///     static FOO: u32 = 42;
+    /// }
+    ///
+    /// # #[cfg(feature = "track-tokens")]
+    /// # stack_tokens::check_untracked_borrows(false);
+    /// make_guard!(guard);
+    /// let token = unsafe { StackToken::from_guard(&guard) };
+    /// assert_eq!(*FOO.borrow(token), 42);
+    /// ```
+    ///
+    /// Like [`new_unchecked`](Self::new_unchecked), tokens created this way
+    /// are not counted by the `track-tokens` feature, so borrowing with them
+    /// requires turning off the check with `check_untracked_borrows`.
+    ///
+    /// # Safety
+    ///
+    /// The guard must have been created with `make_guard!` in a stack frame
+    /// of the current thread.  Guards are [`Send`] and [`Sync`], so a guard
+    /// borrowed from another thread could otherwise be used to hold on to a
+    /// reference into that thread's thread locals after it shut down.
+    #[inline]
+    #[must_use]
+    pub unsafe fn from_guard<'guard>(guard: &'guard Guard<'id>) -> &'guard StackToken<'id> {
+        let _ = guard;
+        // SAFETY: tokens are zero sized, so any aligned non-null pointer is
+        // valid to dereference.
+        NonNull::<StackToken<'id>>::dangling().as_ref()
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_generativity_from_guard() {
+    use crate::LocalKeyExt;
+
+    thread_local! { static FOO: Vec<u32> = vec![1, 2, 3]; }
+
+    fn sum<'id>(token: &StackToken<'id>, _guard: &Guard<'id>) -> u32 {
+        FOO.borrow(token).iter().sum()
+    }
+
+    #[cfg(feature = "track-tokens")]
+    crate::check_untracked_borrows(false);
+    generativity::make_guard!(guard);
+    let token = unsafe { StackToken::from_guard(&guard) };
+    assert_eq!(sum(token, &guard), 6);
+    #[cfg(feature = "track-tokens")]
+    crate::check_untracked_borrows(true);
+}
@@ -22,7 +22,7 @@
 //! # Ref Cells
 //!
 //! This example shows how stack tokens can be used with the
-//! [`RefCellLocalKeyExt`] extension trait to directly borrow into [`RefCell`]s
+//! [`RefCellLocalKeyExt`] extension trait to directly borrow into [`RefCell`](std::cell::RefCell)s
 //! in a thread local.
 //!
 //! ```
@@ -63,6 +63,48 @@
 //! assert_eq!(COUNTER.borrow(scope).load(Ordering::Acquire), 1);
 //! ```
 //!
+//! # Feature Flags
+//!
+//! The [`StackToken`] type and the [`stack_token!`] macro only depend on
+//! `core` and are always available.  The extension traits for thread locals
+//! require the standard library and are gated behind the `std` feature which
+//! is enabled by default.  Disable default features to use the crate in
+//! `no_std` environments.
+//!
+//! The `derive` feature enables a derive for the [`StackBorrow`] trait.
+//!
+//! The `parking_lot` feature adds `ParkingLotMutexLocalKeyExt` and
+//! `ParkingLotRwLockLocalKeyExt` for thread locals holding the locks of the
+//! `parking_lot` crate.
+//!
+//! The `loom` feature adds `LoomLocalKeyExt` and the lock and atomic
+//! extensions for thread locals declared with `loom::thread_local!`, so that
+//! code built on stack tokens can be checked with `loom`.
+//!
+//! The `thread_local_crate` feature adds `ThreadLocalExt` for the
+//! `ThreadLocal` type of the `thread_local` crate.
+//!
+//! The `serde` feature adds `SerdeRefCellLocalKeyExt` to serialize the value
+//! of a [`RefCell`](std::cell::RefCell) thread local.
+//!
+//! The `tokio` feature adds `TaskLocalKeyExt` for the synchronous sections
+//! of tasks using `tokio::task_local!`.
+//!
+//! The `once_cell` feature implements `OnceCellLocalKeyExt` for
+//! `once_cell::unsync::OnceCell` and `OnceLockLocalKeyExt` for
+//! `once_cell::sync::OnceCell`.
+//!
+//! The `generativity` feature adds `StackToken::from_guard` to create a token
+//! that shares its brand with a `generativity::Guard`.
+//!
+//! The `track-tokens` feature enables a diagnostic for debug builds.  Every
+//! token created by [`stack_token!`] is counted in a thread local and
+//! borrowing a thread local asserts that at least one token is alive.
+//! Tokens created with [`StackToken::new_unchecked`] are not counted, so code
+//! using them can turn the assertion off with [`check_untracked_borrows`].
+//! In release builds the feature does nothing.  The number of live tokens can
+//! be inspected with [`current_token_depth`].
+//!
 //! # Implementing Stack Local APIs
 //!
 //! To implement your own methods that use stack tokens introduce a new lifetime
@@ -81,92 +123,821 @@
 //!     }
 //! }
 //! ```
-use std::cell::{Ref, RefCell, RefMut};
-use std::marker::PhantomData;
-use std::mem::transmute;
-use std::thread::LocalKey;
+//!
+//! To make such proxies usable from generic code they can also implement the
+//! [`StackBorrow`] trait.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::marker::PhantomData;
+
+#[cfg(feature = "std")]
+mod tls;
+
+#[cfg(feature = "std")]
+pub use self::tls::*;
+
+#[cfg(feature = "parking_lot")]
+mod parking_lot;
+
+#[cfg(feature = "parking_lot")]
+pub use self::parking_lot::*;
+
+#[cfg(feature = "loom")]
+mod loom;
+
+#[cfg(feature = "loom")]
+pub use self::loom::*;
+
+#[cfg(feature = "thread_local_crate")]
+mod thread_local_crate;
+
+#[cfg(feature = "thread_local_crate")]
+pub use self::thread_local_crate::*;
+
+#[cfg(feature = "serde")]
+mod serde;
+
+#[cfg(feature = "serde")]
+pub use self::serde::*;
+
+#[cfg(feature = "tokio")]
+mod tokio;
+
+#[cfg(feature = "tokio")]
+pub use self::tokio::*;
+
+#[cfg(feature = "once_cell")]
+mod once_cell;
+
+#[cfg(feature = "generativity")]
+mod generativity;
 
 /// A token to bind lifetimes to a specific stack.
 ///
+/// Every token created with [`stack_token!`] carries a unique invariant brand
+/// lifetime `'id` which is bound to the scope the token was declared in.  As
+/// a result two tokens are never interchangeable and a reference borrowed
+/// with a token can never outlive the scope of that token.
+///
+/// Tokens have no identity at runtime.  They are zero sized and different
+/// tokens are free to share the same address, so comparing token references
+/// with [`ptr::eq`](core::ptr::eq) does not say anything about whether they
+/// are the same token.  Instead the brand can be used to have the compiler
+/// enforce that two tokens are the same:
+///
+/// ```
+/// use stack_tokens::{stack_token, StackToken};
+///
+/// fn same_token<'id>(a: &StackToken<'id>, b: &StackToken<'id>) {
+///     let _ = (a, b);
+/// }
+///
+/// stack_token!(scope);
+/// same_token(scope, scope);
+/// ```
+///
+/// Tokens are neither [`Send`] nor [`Sync`].  This is load-bearing for the
+/// soundness of the crate as references into thread locals are bound to the
+/// token's lifetime.  If a token could be sent to or shared with another
+/// thread, that thread could use it to hold on to a reference into the
+/// thread locals of the original thread after it shut down.
+///
 /// For more information see [`stack_token`].
-pub struct StackToken {
+pub struct StackToken<'id> {
     _marker: PhantomData<*const ()>,
+    _brand: PhantomData<fn(&'id ()) -> &'id ()>,
 }
 
-impl StackToken {
-    #[doc(hidden)]
-    pub unsafe fn __private_new() -> StackToken {
+impl<'id> StackToken<'id> {
+    /// Creates a new token without binding it to a scope.
+    ///
+    /// This is the building block for [`stack_token!`] and can be used to
+    /// build custom token scoping constructs.  Prefer the macro where
+    /// possible.
+    ///
+    /// ```
+    /// use stack_tokens::{StackToken, StackTokenCell};
+    ///
+    /// let cell = StackTokenCell::new(42);
+    /// let token = unsafe { StackToken::new_unchecked() };
+    /// assert_eq!(*cell.borrow(&token), 42);
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// The token must stay in a local variable of the calling stack frame.
+    /// It must not be moved to the heap, leaked, returned or otherwise made
+    /// to outlive that frame, as references borrowed with it would then
+    /// outlive the thread locals they point into.  The brand `'id` is not
+    /// tied to anything, so it is up to the caller to keep tokens apart
+    /// where that matters (see [`stack_token!`] for how the macro does it).
+    #[must_use]
+    pub unsafe fn new_unchecked() -> StackToken<'id> {
+        StackToken::const_new()
+    }
+
+    /// Creates a new token in a `const` context.
+    ///
+    /// This is the same as [`new_unchecked`](Self::new_unchecked) but can be
+    /// called from `const` blocks and items, which is useful for macro
+    /// expansions that need a token in const position.
+    ///
+    /// ```
+    /// use stack_tokens::{StackToken, StackTokenCell};
+    ///
+    /// let cell = StackTokenCell::new(42);
+    /// let token = const { unsafe { StackToken::const_new() } };
+    /// assert_eq!(*cell.borrow(&token), 42);
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`new_unchecked`](Self::new_unchecked) apply.
+    /// A `const` holding a token must only be used as a local of the stack
+    /// frame it is evaluated in.  Storing a token in a `static` (or a thread
+    /// local) defeats the purpose of the token and is unsound, as references
+    /// borrowed with it would no longer be bound to a stack frame.
+    #[must_use]
+    pub const unsafe fn const_new() -> StackToken<'id> {
         StackToken {
             _marker: PhantomData,
+            _brand: PhantomData,
+        }
+    }
+
+    /// Reborrows the token with a shorter lifetime.
+    ///
+    /// This is a no-op at runtime, but it can help to pass a token into
+    /// generic code that wants a borrow shorter than the one held by the
+    /// caller:
+    ///
+    /// ```
+    /// use stack_tokens::{stack_token, LocalKeyExt, StackToken};
+    ///
+    /// thread_local! {
+    ///     static FOO: u32 = 42;
+    /// }
+    ///
+    /// fn helper<'short>(token: &'short StackToken) -> &'short u32 {
+    ///     FOO.borrow(token)
+    /// }
+    ///
+    /// stack_token!(scope);
+    /// let value = helper(scope.reborrow());
+    /// assert_eq!(*value, *FOO.borrow(scope));
+    /// ```
+    #[inline]
+    pub fn reborrow(&self) -> &StackToken<'id> {
+        self
+    }
+}
+
+impl<'id> fmt::Debug for StackToken<'id> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StackToken").finish()
+    }
+}
+
+/// Returns how many tokens are currently alive on this thread.
+///
+/// Only tokens created by the macros are counted, [`stack_tokens!`] counts
+/// every token it creates.  This can help to find
+/// accidental token nesting in recursive code.  In release builds tokens are
+/// not tracked and this always returns `0`.
+///
+/// This function is only available with the `track-tokens` feature.
+#[cfg(feature = "track-tokens")]
+pub fn current_token_depth() -> usize {
+    #[cfg(debug_assertions)]
+    {
+        __private::active_tokens()
+    }
+    #[cfg(not(debug_assertions))]
+    {
+        0
+    }
+}
+
+/// Enables or disables the assertion for borrows without a tracked token
+/// on the current thread.
+///
+/// With the `track-tokens` feature debug builds panic if a thread local is
+/// borrowed while no token created by the macros is alive.  Code that
+/// creates its tokens with [`StackToken::new_unchecked`] can turn this off
+/// for the threads it runs on.  The check is enabled by default.
+///
+/// This function is only available with the `track-tokens` feature.
+#[cfg(feature = "track-tokens")]
+pub fn check_untracked_borrows(enabled: bool) {
+    #[cfg(debug_assertions)]
+    __private::CHECK_UNTRACKED.with(|check| check.set(enabled));
+    let _ = enabled;
+}
+
+#[doc(hidden)]
+pub mod __private {
+    use super::*;
+
+    /// Pins the brand of a token to the end of the enclosing scope.
+    pub struct BrandGuard<'id> {
+        _brand: PhantomData<fn(&'id ()) -> &'id ()>,
+        count: usize,
+    }
+
+    impl<'id> BrandGuard<'id> {
+        #[inline]
+        pub fn new(token: &'id StackToken<'id>) -> BrandGuard<'id> {
+            let _ = token;
+            track_tokens(1, true);
+            BrandGuard {
+                _brand: PhantomData,
+                count: 1,
+            }
+        }
+
+        #[inline]
+        pub fn new_scoped<const N: usize>(tokens: &'id ScopedTokens<'id, N>) -> BrandGuard<'id> {
+            let _ = tokens;
+            track_tokens(N, true);
+            BrandGuard {
+                _brand: PhantomData,
+                count: N,
+            }
+        }
+    }
+
+    impl<'id> Drop for BrandGuard<'id> {
+        #[inline]
+        fn drop(&mut self) {
+            track_tokens(self.count, false);
         }
     }
+
+    #[cfg(all(feature = "track-tokens", debug_assertions))]
+    std::thread_local! {
+        static ACTIVE_TOKENS: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+    }
+
+    #[inline]
+    fn track_tokens(count: usize, created: bool) {
+        #[cfg(all(feature = "track-tokens", debug_assertions))]
+        ACTIVE_TOKENS.with(|active| {
+            active.set(if created {
+                active.get() + count
+            } else {
+                active.get() - count
+            })
+        });
+        let _ = (count, created);
+    }
+
+    /// Returns the number of tokens created by the macros that are alive on
+    /// the current thread.
+    #[cfg(all(feature = "track-tokens", debug_assertions))]
+    pub fn active_tokens() -> usize {
+        ACTIVE_TOKENS.with(|count| count.get())
+    }
+
+    #[cfg(all(feature = "track-tokens", debug_assertions))]
+    std::thread_local! {
+        pub static CHECK_UNTRACKED: core::cell::Cell<bool> = const { core::cell::Cell::new(true) };
+    }
+
+    /// Asserts that a token created by the macros is alive when a thread
+    /// local is borrowed.
+    #[cfg(all(feature = "track-tokens", debug_assertions))]
+    #[track_caller]
+    pub fn check_untracked_borrow() {
+        assert!(
+            active_tokens() > 0 || !CHECK_UNTRACKED.with(|check| check.get()),
+            "thread local borrowed without an active stack token"
+        );
+    }
 }
 
 /// Creates a new [`StackToken`] with a given name on the stack.
+///
+/// Multiple tokens can be declared at once by separating the names with
+/// commas:
+///
+/// ```
+/// use stack_tokens::{stack_token, LocalKeyExt};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// thread_local! {
+///     static A: AtomicUsize = AtomicUsize::new(1);
+///     static B: AtomicUsize = AtomicUsize::new(2);
+///     static C: AtomicUsize = AtomicUsize::new(3);
+/// }
+///
+/// stack_token!(a, b, c);
+///
+/// let sum = A.borrow(a).load(Ordering::Relaxed)
+///     + B.borrow(b).load(Ordering::Relaxed)
+///     + C.borrow(c).load(Ordering::Relaxed);
+/// assert_eq!(sum, 6);
+/// ```
+///
+/// The token itself lives in a hidden local until the end of the enclosing
+/// scope and every borrow made with it must end before that.  The binding
+/// is only a reference to that local, so shadowing it or moving the
+/// reference elsewhere does not end the token early.
 #[macro_export]
 macro_rules! stack_token {
     ($name:ident) => {
         #[allow(unsafe_code)]
-        let $name = &unsafe { $crate::StackToken::__private_new() };
+        let token = unsafe { $crate::StackToken::new_unchecked() };
+        let _brand_guard = $crate::__private::BrandGuard::new(&token);
+        let $name = &token;
+    };
+    ($($name:ident),+ $(,)?) => {
+        $($crate::stack_token!($name);)+
     };
 }
 
-/// Adds [`StackToken`] support to the standard library's [`LocalKey`].
-pub trait LocalKeyExt<T> {
-    /// Borrows the value from the TLS with a [`StackToken`].
-    fn borrow<'stack>(&'static self, token: &'stack StackToken) -> &'stack T;
+/// Evaluates an expression with a fresh [`StackToken`].
+///
+/// The token is only in scope for the body and the macro evaluates to the
+/// value of the body.  This is useful if a token is only needed for a short
+/// block:
+///
+/// ```
+/// use stack_tokens::{with_stack_token, RefCellLocalKeyExt};
+/// use std::cell::RefCell;
+///
+/// thread_local! {
+///     static VEC: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]);
+/// }
+///
+/// let sum: i32 = with_stack_token!(|scope| {
+///     VEC.as_ref(scope).iter().sum()
+/// });
+/// assert_eq!(sum, 6);
+/// ```
+#[macro_export]
+macro_rules! with_stack_token {
+    (|$name:ident| $body:expr) => {{
+        $crate::stack_token!($name);
+        let rv = $body;
+        rv
+    }};
+}
+
+/// Evaluates a block with a fresh [`StackToken`].
+///
+/// This is the block form of [`with_stack_token!`].  The token is declared
+/// at the top of the block and the block evaluates to the value of its
+/// last expression.  As the token does not outlive the block, the borrow
+/// checker rejects any attempt to let a reference borrowed with it escape:
+///
+/// ```
+/// use stack_tokens::{token_scope, LocalKeyExt};
+///
+/// thread_local! {
+///     static FOO: u32 = 42;
+/// }
+///
+/// let value = token_scope! {
+///     scope;
+///     let value = FOO.borrow(scope);
+///     *value + 1
+/// };
+/// assert_eq!(value, 43);
+/// ```
+#[macro_export]
+macro_rules! token_scope {
+    ($name:ident; $($body:tt)*) => {{
+        $crate::stack_token!($name);
+        let rv = { $($body)* };
+        rv
+    }};
 }
 
-impl<T: 'static> LocalKeyExt<T> for LocalKey<T> {
-    fn borrow<'stack>(&'static self, token: &'stack StackToken) -> &'stack T {
+/// Evaluates an expression with two fresh, distinct [`StackToken`]s.
+///
+/// This works like [`with_stack_token!`] but declares two tokens with
+/// different brands.  This is useful if a function that needs its own token
+/// is called while a borrow with the outer token is still held:
+///
+/// ```
+/// use stack_tokens::{with_two_tokens, LocalKeyExt};
+///
+/// thread_local! {
+///     static FOO: u32 = 23;
+///     static BAR: u32 = 42;
+/// }
+///
+/// let sum = with_two_tokens!(outer, inner => {
+///     let foo = FOO.borrow(outer);
+///     let bar = BAR.borrow(inner);
+///     *foo + *bar
+/// });
+/// assert_eq!(sum, 65);
+/// ```
+///
+/// The two tokens are not interchangeable:
+///
+/// ```compile_fail
+/// use stack_tokens::{with_two_tokens, StackToken};
+///
+/// fn same_token<'id>(a: &StackToken<'id>, b: &StackToken<'id>) {
+///     let _ = (a, b);
+/// }
+///
+/// with_two_tokens!(outer, inner => same_token(outer, inner));
+/// ```
+#[macro_export]
+macro_rules! with_two_tokens {
+    ($outer:ident, $inner:ident => $body:expr) => {{
+        $crate::stack_token!($outer, $inner);
+        let rv = $body;
+        rv
+    }};
+}
+
+/// A fixed number of [`StackToken`]s on the stack.
+///
+/// This is useful for generic code where the number of tokens needed is
+/// driven by a const generic rather than hardcoded.  All tokens of one set
+/// share the same brand.  Use [`stack_tokens!`] to create one.
+pub struct ScopedTokens<'id, const N: usize> {
+    tokens: [StackToken<'id>; N],
+}
+
+impl<'id, const N: usize> ScopedTokens<'id, N> {
+    #[doc(hidden)]
+    #[must_use]
+    pub unsafe fn __private_new() -> ScopedTokens<'id, N> {
+        ScopedTokens {
+            tokens: [(); N].map(|_| StackToken::new_unchecked()),
+        }
+    }
+
+    /// Returns the token at the given index.
+    ///
+    /// Returns `None` if the index is out of bounds.
+    #[inline]
+    pub fn get(&self, idx: usize) -> Option<&StackToken<'id>> {
+        self.tokens.get(idx)
+    }
+
+    /// Returns the number of tokens.
+    #[inline]
+    pub fn len(&self) -> usize {
+        N
+    }
+
+    /// Returns `true` if there are no tokens.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        N == 0
+    }
+}
+
+impl<'id, const N: usize> fmt::Debug for ScopedTokens<'id, N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScopedTokens").field("len", &N).finish()
+    }
+}
+
+/// Creates a [`ScopedTokens`] with a given name and number of tokens.
+///
+/// ```
+/// use stack_tokens::{stack_tokens, LocalKeyExt};
+///
+/// thread_local! {
+///     static FOO: u32 = 42;
+/// }
+///
+/// stack_tokens!(tokens, 3);
+/// for idx in 0..tokens.len() {
+///     assert_eq!(*FOO.borrow(tokens.get(idx).unwrap()), 42);
+/// }
+/// assert!(tokens.get(3).is_none());
+/// ```
+#[macro_export]
+macro_rules! stack_tokens {
+    ($name:ident, $n:expr) => {
+        #[allow(unsafe_code)]
+        let tokens = unsafe { $crate::ScopedTokens::<{ $n }>::__private_new() };
+        let _brand_guard = $crate::__private::BrandGuard::new_scoped(&tokens);
+        let $name = &tokens;
+    };
+}
+
+/// A common interface for values that can lend out references with a [`StackToken`].
+///
+/// This is implemented for [`LocalKey`](std::thread::LocalKey) and can be
+/// implemented by custom proxy types which want to hand out references bound
+/// to the lifetime of a stack token.
+///
+/// ```
+/// use stack_tokens::{stack_token, StackBorrow, StackToken};
+///
+/// struct Proxy(&'static str);
+///
+/// impl StackBorrow for Proxy {
+///     type Target = str;
+///
+///     fn stack_borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack str {
+///         let _ = token;
+///         self.0
+///     }
+/// }
+///
+/// stack_token!(scope);
+/// assert_eq!(Proxy("Hello").stack_borrow(scope), "Hello");
+/// ```
+///
+/// Generic code can accept any `P: StackBorrow` to work uniformly with
+/// thread locals, [`StackTokenCell`]s and references to either of them.
+/// To also accept plain references use [`StackRef`] instead.
+pub trait StackBorrow {
+    /// The type of the value that is borrowed.
+    type Target: ?Sized;
+
+    /// Borrows the target value with a [`StackToken`].
+    fn stack_borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack Self::Target;
+}
+
+/// A container that lends out its value with a [`StackToken`].
+///
+/// This gives proxies that are not backed by thread locals the same
+/// borrowing pattern as the thread local extension traits.  For instance a
+/// proxy placed in a [`Box::leak`]ed allocation can hand out references that
+/// are bound to a stack token rather than `'static`.
+///
+/// The soundness contract of the cell is that shared references handed out
+/// by [`borrow`](Self::borrow) are bound to both the cell and the token and
+/// that the value can only be modified through exclusive access to the cell
+/// (see [`get_mut`](Self::get_mut)).  The cell is not [`Sync`].
+///
+/// ```
+/// use stack_tokens::{stack_token, StackTokenCell};
+///
+/// let cell: &'static StackTokenCell<Vec<i32>> =
+///     Box::leak(Box::new(StackTokenCell::new(vec![1, 2, 3])));
+///
+/// stack_token!(scope);
+/// assert_eq!(cell.borrow(scope).len(), 3);
+/// ```
+pub struct StackTokenCell<T: ?Sized> {
+    value: UnsafeCell<T>,
+}
+
+impl<T> StackTokenCell<T> {
+    /// Creates a new cell containing the given value.
+    #[inline]
+    pub const fn new(value: T) -> StackTokenCell<T> {
+        StackTokenCell {
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Consumes the cell, returning the wrapped value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized> StackTokenCell<T> {
+    /// Borrows the contained value with a [`StackToken`].
+    #[inline]
+    pub fn borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T {
         let _ = token;
-        self.with(|value| unsafe { transmute::<&T, &'stack T>(value) })
+        // the value is only ever mutated through `&mut self`
+        unsafe { &*self.value.get() }
+    }
+
+    /// Returns a mutable reference to the contained value.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
     }
 }
 
-/// Additional utility methods to [`LocalKey`]s holding [`RefCell`] values.
+impl<T: ?Sized> StackBorrow for StackTokenCell<T> {
+    type Target = T;
+
+    #[inline]
+    fn stack_borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T {
+        self.borrow(token)
+    }
+}
+
+impl<T: Default> Default for StackTokenCell<T> {
+    fn default() -> StackTokenCell<T> {
+        StackTokenCell::new(T::default())
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for StackTokenCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StackTokenCell").finish_non_exhaustive()
+    }
+}
+
+impl<P: StackBorrow + ?Sized> StackBorrow for &P {
+    type Target = P::Target;
+
+    #[inline]
+    fn stack_borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack P::Target {
+        (**self).stack_borrow(token)
+    }
+}
+
+/// A common interface for thread locals and plain references.
+///
+/// Unlike [`StackBorrow`], which forwards through references, a plain
+/// reference `&T` is itself a [`StackRef`] for `T`.  This lets generic code
+/// accept values that are borrowed with a token and values the caller
+/// already holds a reference to:
+///
+/// ```
+/// use stack_tokens::{stack_token, StackRef};
 ///
-/// This extension traits provides the two methods [`as_ref`](Self::as_ref)
-/// and [`as_mut`](Self::as_mut) that let you directly borrow into the
-/// contained [`RefCell`] with a [`StackToken`].
-pub trait RefCellLocalKeyExt<T> {
-    /// Acquires a reference to the contained value.
-    fn as_ref<'stack>(&'static self, token: &'stack StackToken) -> Ref<'stack, T>;
+/// thread_local! {
+///     static NAME: String = "Peter".into();
+/// }
+///
+/// fn greet<R: StackRef<Target = String> + ?Sized>(name: &R) -> String {
+///     stack_token!(scope);
+///     format!("Hello {}!", name.stack_ref(scope))
+/// }
+///
+/// let other = String::from("Paul");
+/// assert_eq!(greet(&NAME), "Hello Peter!");
+/// assert_eq!(greet(&&other), "Hello Paul!");
+/// ```
+pub trait StackRef {
+    /// The type of the value that is referenced.
+    type Target: ?Sized;
 
-    /// Acquires a mutable reference to the contained value.
-    fn as_mut<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, T>;
+    /// Returns a reference to the target value bound to a [`StackToken`].
+    fn stack_ref<'stack>(&'stack self, token: &'stack StackToken) -> &'stack Self::Target;
 }
 
-impl<T: 'static> RefCellLocalKeyExt<T> for LocalKey<RefCell<T>> {
-    fn as_ref<'stack>(&'static self, token: &'stack StackToken) -> Ref<'stack, T> {
-        self.borrow(token).borrow()
+impl<T: ?Sized> StackRef for &T {
+    type Target = T;
+
+    #[inline]
+    fn stack_ref<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T {
+        let _ = token;
+        self
     }
+}
 
-    fn as_mut<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, T> {
-        self.borrow(token).borrow_mut()
+impl<T: ?Sized> StackRef for StackTokenCell<T> {
+    type Target = T;
+
+    #[inline]
+    fn stack_ref<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T {
+        self.borrow(token)
     }
 }
 
+/// Derives [`StackBorrow`] for a struct wrapping a single field.
+///
+/// The derived implementation forwards to the implementation of the field.
+/// This requires the `derive` feature.
+#[cfg(feature = "derive")]
+pub use stack_tokens_derive::StackBorrow;
+
 #[test]
-fn test_tls_basic() {
-    use crate::stack_token;
-    use std::cell::RefCell;
+fn test_brand_same_token() {
+    fn same_scope<'id>(_a: &StackToken<'id>, _b: &StackToken<'id>) {}
 
-    thread_local! { static FOO: RefCell<u32> = RefCell::default(); }
+    stack_token!(scope);
+    same_scope(scope, scope);
+}
+
+#[test]
+fn test_core_token() {
+    fn takes_token(token: &StackToken) -> usize {
+        core::mem::size_of_val(token)
+    }
 
     stack_token!(scope);
-    *FOO.borrow(scope).borrow_mut() += 1;
-    assert_eq!(*FOO.borrow(scope).borrow(), 1);
+    assert_eq!(takes_token(scope), 0);
+}
+
+#[test]
+fn test_custom_stack_borrow() {
+    struct Counter {
+        values: &'static [u32],
+    }
+
+    impl StackBorrow for Counter {
+        type Target = [u32];
+
+        fn stack_borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack [u32] {
+            let _ = token;
+            self.values
+        }
+    }
+
+    fn sum<P: StackBorrow<Target = [u32]>>(proxy: &P) -> u32 {
+        stack_token!(scope);
+        proxy.stack_borrow(scope).iter().sum()
+    }
+
+    assert_eq!(sum(&Counter { values: &[1, 2, 3] }), 6);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_debug() {
+    let token = unsafe { StackToken::new_unchecked() };
+    assert_eq!(format!("{:?}", token), "StackToken");
+}
+
+#[test]
+#[cfg(all(feature = "track-tokens", debug_assertions))]
+fn test_track_tokens() {
+    let before = current_token_depth();
+    {
+        stack_token!(a);
+        assert_eq!(current_token_depth(), before + 1);
+        {
+            stack_token!(b, c);
+            let _ = (a, b, c);
+            assert_eq!(current_token_depth(), before + 3);
+        }
+        assert_eq!(current_token_depth(), before + 1);
+        {
+            stack_tokens!(tokens, 3);
+            let _ = tokens;
+            assert_eq!(current_token_depth(), before + 4);
+        }
+        assert_eq!(current_token_depth(), before + 1);
+    }
+    assert_eq!(current_token_depth(), before);
 }
 
 #[test]
-fn test_tls_ref_cell() {
-    use crate::stack_token;
-    use std::cell::RefCell;
+fn test_new_unchecked() {
+    let cell = StackTokenCell::new([1, 2, 3]);
 
-    thread_local! { static FOO: RefCell<u32> = RefCell::default(); }
+    let token = unsafe { StackToken::new_unchecked() };
+    assert_eq!(cell.borrow(&token).len(), 3);
+    assert_eq!(cell.stack_borrow(token.reborrow()), &[1, 2, 3]);
+}
+
+#[test]
+fn test_const_new() {
+    let cell = StackTokenCell::new(42);
+    let token = const { unsafe { StackToken::const_new() } };
+    assert_eq!(*cell.borrow(&token), 42);
+}
+
+#[test]
+fn test_not_send_sync() {
+    // fails to compile with an ambiguity error if `$ty` implements `$trait`.
+    macro_rules! assert_not_impl {
+        ($ty:ty: $trait:path) => {{
+            trait AmbiguousIfImpl<A> {
+                fn some_item() {}
+            }
+            impl<T: ?Sized> AmbiguousIfImpl<()> for T {}
+            #[allow(dead_code)]
+            struct Invalid;
+            impl<T: ?Sized + $trait> AmbiguousIfImpl<Invalid> for T {}
+            let _ = <$ty as AmbiguousIfImpl<_>>::some_item;
+        }};
+    }
+
+    assert_not_impl!(StackToken<'static>: Send);
+    assert_not_impl!(StackToken<'static>: Sync);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_stack_token_cell() {
+    let cell: &'static StackTokenCell<Vec<i32>> =
+        Box::leak(Box::new(StackTokenCell::new(vec![1, 2, 3])));
 
     stack_token!(scope);
-    *FOO.as_mut(scope) += 1;
-    assert_eq!(*FOO.as_ref(scope), 1);
+    let values = cell.borrow(scope);
+    assert_eq!(values, &[1, 2, 3]);
+    assert_eq!(cell.stack_borrow(scope).len(), 3);
+
+    let mut cell = StackTokenCell::new(1);
+    *cell.get_mut() += 1;
+    assert_eq!(*cell.borrow(scope), 2);
+    assert_eq!(cell.into_inner(), 2);
+}
+
+#[test]
+fn test_scoped_tokens() {
+    let cell = StackTokenCell::new(42);
+
+    stack_tokens!(tokens, 3);
+    assert_eq!(tokens.len(), 3);
+    for idx in 0..3 {
+        assert_eq!(*cell.borrow(tokens.get(idx).unwrap()), 42);
+    }
+    assert!(tokens.get(3).is_none());
 }
@@ -0,0 +1,260 @@
+use std::sync::atomic::Ordering;
+use std::sync::{LockResult, TryLockResult};
+
+use loom::sync::atomic::{
+    AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicIsize, AtomicU16, AtomicU32, AtomicU64,
+    AtomicU8, AtomicUsize,
+};
+use loom::sync::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use loom::thread::LocalKey;
+
+use crate::tls::bind_to_token;
+use crate::{AtomicLocalKeyExt, StackToken};
+
+/// Adds [`StackToken`] support to the thread locals of `loom`.
+///
+/// This is the equivalent of [`LocalKeyExt`](crate::LocalKeyExt) for thread
+/// locals declared with `loom::thread_local!` and lets code that uses stack
+/// tokens be checked with `loom`.  Like all `loom` types these can only be
+/// used from within `loom::model`.
+pub trait LoomLocalKeyExt<T> {
+    /// Borrows the value from the TLS with a [`StackToken`].
+    fn borrow<'stack>(&'static self, token: &'stack StackToken) -> &'stack T;
+}
+
+impl<T: 'static> LoomLocalKeyExt<T> for LocalKey<T> {
+    #[inline]
+    #[track_caller]
+    fn borrow<'stack>(&'static self, token: &'stack StackToken) -> &'stack T {
+        // the value is owned by the current model thread and only dropped
+        // once that thread finishes
+        self.with(|value| unsafe { bind_to_token(value, token) })
+    }
+}
+
+/// Additional utility methods to `loom` thread locals holding a
+/// [`loom::sync::Mutex`].
+///
+/// This is the equivalent of [`MutexLocalKeyExt`](crate::MutexLocalKeyExt)
+/// for `loom`.
+pub trait LoomMutexLocalKeyExt<T> {
+    /// Acquires the mutex, blocking the current thread until it is able to do so.
+    fn lock<'stack>(&'static self, token: &'stack StackToken) -> LockResult<MutexGuard<'stack, T>>;
+
+    /// Attempts to acquire the mutex without blocking.
+    fn try_lock<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<MutexGuard<'stack, T>>;
+}
+
+impl<T: 'static> LoomMutexLocalKeyExt<T> for LocalKey<Mutex<T>> {
+    #[inline]
+    #[track_caller]
+    fn lock<'stack>(&'static self, token: &'stack StackToken) -> LockResult<MutexGuard<'stack, T>> {
+        self.borrow(token).lock()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_lock<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<MutexGuard<'stack, T>> {
+        self.borrow(token).try_lock()
+    }
+}
+
+/// Additional utility methods to `loom` thread locals holding a
+/// [`loom::sync::RwLock`].
+///
+/// This is the equivalent of [`RwLockLocalKeyExt`](crate::RwLockLocalKeyExt)
+/// for `loom`.
+pub trait LoomRwLockLocalKeyExt<T> {
+    /// Locks the lock with shared read access.
+    fn read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> LockResult<RwLockReadGuard<'stack, T>>;
+
+    /// Locks the lock with exclusive write access.
+    fn write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> LockResult<RwLockWriteGuard<'stack, T>>;
+
+    /// Attempts to lock the lock with shared read access without blocking.
+    fn try_read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<RwLockReadGuard<'stack, T>>;
+
+    /// Attempts to lock the lock with exclusive write access without blocking.
+    fn try_write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<RwLockWriteGuard<'stack, T>>;
+}
+
+impl<T: 'static> LoomRwLockLocalKeyExt<T> for LocalKey<RwLock<T>> {
+    #[inline]
+    #[track_caller]
+    fn read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> LockResult<RwLockReadGuard<'stack, T>> {
+        self.borrow(token).read()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> LockResult<RwLockWriteGuard<'stack, T>> {
+        self.borrow(token).write()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<RwLockReadGuard<'stack, T>> {
+        self.borrow(token).try_read()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<RwLockWriteGuard<'stack, T>> {
+        self.borrow(token).try_write()
+    }
+}
+
+macro_rules! impl_loom_atomic_local_key_ext {
+    ($($atomic:ident => $ty:ty,)*) => {
+        $(
+            impl AtomicLocalKeyExt<$ty> for LocalKey<$atomic> {
+                #[inline]
+                #[track_caller]
+                fn load(&'static self, token: &StackToken, order: Ordering) -> $ty {
+                    self.borrow(token).load(order)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn store(&'static self, token: &StackToken, value: $ty, order: Ordering) {
+                    self.borrow(token).store(value, order)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn swap(&'static self, token: &StackToken, value: $ty, order: Ordering) -> $ty {
+                    self.borrow(token).swap(value, order)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn fetch_add(
+                    &'static self,
+                    token: &StackToken,
+                    value: $ty,
+                    order: Ordering,
+                ) -> $ty {
+                    self.borrow(token).fetch_add(value, order)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn compare_exchange(
+                    &'static self,
+                    token: &StackToken,
+                    current: $ty,
+                    new: $ty,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$ty, $ty> {
+                    self.borrow(token).compare_exchange(current, new, success, failure)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn fetch_update<F: FnMut($ty) -> Option<$ty>>(
+                    &'static self,
+                    token: &StackToken,
+                    set_order: Ordering,
+                    fetch_order: Ordering,
+                    f: F,
+                ) -> Result<$ty, $ty> {
+                    self.borrow(token).fetch_update(set_order, fetch_order, f)
+                }
+            }
+        )*
+    };
+}
+
+impl_loom_atomic_local_key_ext! {
+    AtomicI8 => i8,
+    AtomicI16 => i16,
+    AtomicI32 => i32,
+    AtomicI64 => i64,
+    AtomicIsize => isize,
+    AtomicU8 => u8,
+    AtomicU16 => u16,
+    AtomicU32 => u32,
+    AtomicU64 => u64,
+    AtomicUsize => usize,
+}
+
+#[test]
+fn test_loom_mutex() {
+    use crate::stack_token;
+    use loom::sync::Arc;
+
+    loom::thread_local! { static COUNTER: Mutex<u32> = Mutex::new(0); }
+
+    loom::model(|| {
+        let total = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let total = total.clone();
+                loom::thread::spawn(move || {
+                    stack_token!(scope);
+                    let mut counter = COUNTER.lock(scope).unwrap();
+                    *counter += 1;
+                    // every thread sees its own counter
+                    assert_eq!(*counter, 1);
+                    total.fetch_add(*counter as usize, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        stack_token!(scope);
+        assert_eq!(*COUNTER.lock(scope).unwrap(), 0);
+        assert_eq!(total.load(Ordering::SeqCst), 2);
+    });
+}
+
+#[test]
+fn test_loom_atomic() {
+    use crate::stack_token;
+
+    loom::thread_local! { static COUNTER: AtomicUsize = AtomicUsize::new(0); }
+
+    loom::model(|| {
+        let handle = loom::thread::spawn(|| {
+            stack_token!(scope);
+            COUNTER.fetch_add(scope, 1, Ordering::SeqCst)
+        });
+        stack_token!(scope);
+        assert_eq!(COUNTER.fetch_add(scope, 1, Ordering::SeqCst), 0);
+        assert_eq!(handle.join().unwrap(), 0);
+        assert_eq!(COUNTER.load(scope, Ordering::SeqCst), 1);
+    });
+}
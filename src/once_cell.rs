@@ -0,0 +1,101 @@
+use std::thread::LocalKey;
+
+use once_cell::{sync, unsync};
+
+use crate::{LocalKeyExt, OnceCellLocalKeyExt, OnceLockLocalKeyExt, StackToken};
+
+impl<T: 'static> OnceCellLocalKeyExt<T> for LocalKey<unsync::OnceCell<T>> {
+    #[inline]
+    #[track_caller]
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> Option<&'stack T> {
+        self.borrow(token).get()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_init<'stack, F: FnOnce() -> T>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack T {
+        self.borrow(token).get_or_init(f)
+    }
+}
+
+impl<T: 'static> OnceLockLocalKeyExt<T> for LocalKey<sync::OnceCell<T>> {
+    #[inline]
+    #[track_caller]
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> Option<&'stack T> {
+        self.borrow(token).get()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_init<'stack, F: FnOnce() -> T>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack T {
+        self.borrow(token).get_or_init(f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_try_init<'stack, E, F: FnOnce() -> Result<T, E>>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> Result<&'stack T, E> {
+        self.borrow(token).get_or_try_init(f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn is_initialized(&'static self, token: &StackToken) -> bool {
+        self.borrow(token).get().is_some()
+    }
+}
+
+#[test]
+fn test_once_cell_unsync() {
+    use crate::stack_token;
+
+    thread_local! { static FOO: unsync::OnceCell<String> = const { unsync::OnceCell::new() }; }
+
+    stack_token!(scope);
+    assert_eq!(FOO.get(scope), None);
+    let a = FOO.get_or_init(scope, || "Hello".to_string());
+    let b = FOO.get_or_init(scope, || unreachable!());
+    assert_eq!(a, "Hello");
+    assert!(std::ptr::eq(a, b));
+    assert_eq!(FOO.get(scope).map(|x| x.as_str()), Some("Hello"));
+}
+
+#[test]
+fn test_once_cell_sync() {
+    use crate::stack_token;
+
+    thread_local! { static FOO: sync::OnceCell<String> = const { sync::OnceCell::new() }; }
+
+    stack_token!(scope);
+    assert_eq!(FOO.get(scope), None);
+    let a = FOO.get_or_init(scope, || "Hello".to_string());
+    let b = FOO.get_or_init(scope, || unreachable!());
+    assert_eq!(a, "Hello");
+    assert!(std::ptr::eq(a, b));
+    assert_eq!(FOO.get(scope).map(|x| x.as_str()), Some("Hello"));
+}
+
+#[test]
+fn test_once_cell_sync_get_or_try_init() {
+    use crate::stack_token;
+
+    thread_local! { static FOO: sync::OnceCell<u32> = const { sync::OnceCell::new() }; }
+
+    stack_token!(scope);
+    assert_eq!(FOO.get_or_try_init(scope, || Err("nope")), Err("nope"));
+    assert!(!FOO.is_initialized(scope));
+    assert_eq!(FOO.get_or_try_init(scope, || Ok::<_, ()>(42)), Ok(&42));
+    assert_eq!(FOO.get_or_try_init(scope, || Err("nope")), Ok(&42));
+    assert!(FOO.is_initialized(scope));
+}
@@ -0,0 +1,122 @@
+use std::thread::LocalKey;
+
+use parking_lot::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{LocalKeyExt, StackToken};
+
+/// Additional utility methods to [`LocalKey`]s holding [`parking_lot::Mutex`]
+/// values.
+///
+/// This is the equivalent of [`MutexLocalKeyExt`](crate::MutexLocalKeyExt)
+/// for `parking_lot`.  As these locks are never poisoned the guards are
+/// returned directly.
+pub trait ParkingLotMutexLocalKeyExt<T> {
+    /// Acquires the mutex, blocking the current thread until it is able to do so.
+    fn lock<'stack>(&'static self, token: &'stack StackToken) -> MutexGuard<'stack, T>;
+
+    /// Attempts to acquire the mutex without blocking.
+    fn try_lock<'stack>(&'static self, token: &'stack StackToken) -> Option<MutexGuard<'stack, T>>;
+}
+
+impl<T: 'static> ParkingLotMutexLocalKeyExt<T> for LocalKey<Mutex<T>> {
+    #[inline]
+    #[track_caller]
+    fn lock<'stack>(&'static self, token: &'stack StackToken) -> MutexGuard<'stack, T> {
+        self.borrow(token).lock()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_lock<'stack>(&'static self, token: &'stack StackToken) -> Option<MutexGuard<'stack, T>> {
+        self.borrow(token).try_lock()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`parking_lot::RwLock`]
+/// values.
+///
+/// This is the equivalent of [`RwLockLocalKeyExt`](crate::RwLockLocalKeyExt)
+/// for `parking_lot`.
+pub trait ParkingLotRwLockLocalKeyExt<T> {
+    /// Locks the lock with shared read access.
+    fn read<'stack>(&'static self, token: &'stack StackToken) -> RwLockReadGuard<'stack, T>;
+
+    /// Locks the lock with exclusive write access.
+    fn write<'stack>(&'static self, token: &'stack StackToken) -> RwLockWriteGuard<'stack, T>;
+
+    /// Attempts to lock the lock with shared read access without blocking.
+    fn try_read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Option<RwLockReadGuard<'stack, T>>;
+
+    /// Attempts to lock the lock with exclusive write access without blocking.
+    fn try_write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Option<RwLockWriteGuard<'stack, T>>;
+}
+
+impl<T: 'static> ParkingLotRwLockLocalKeyExt<T> for LocalKey<RwLock<T>> {
+    #[inline]
+    #[track_caller]
+    fn read<'stack>(&'static self, token: &'stack StackToken) -> RwLockReadGuard<'stack, T> {
+        self.borrow(token).read()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn write<'stack>(&'static self, token: &'stack StackToken) -> RwLockWriteGuard<'stack, T> {
+        self.borrow(token).write()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Option<RwLockReadGuard<'stack, T>> {
+        self.borrow(token).try_read()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Option<RwLockWriteGuard<'stack, T>> {
+        self.borrow(token).try_write()
+    }
+}
+
+#[test]
+fn test_parking_lot_mutex() {
+    use crate::stack_token;
+
+    thread_local! { static FOO: Mutex<Vec<u32>> = const { Mutex::new(Vec::new()) }; }
+
+    stack_token!(scope);
+    FOO.lock(scope).push(42);
+    let guard = FOO.lock(scope);
+    assert_eq!(*guard, vec![42]);
+    assert!(FOO.try_lock(scope).is_none());
+    drop(guard);
+    assert!(FOO.try_lock(scope).is_some());
+}
+
+#[test]
+fn test_parking_lot_rwlock() {
+    use crate::stack_token;
+
+    thread_local! { static FOO: RwLock<u32> = const { RwLock::new(1) }; }
+
+    stack_token!(scope);
+    *FOO.write(scope) += 1;
+    let a = FOO.read(scope);
+    let b = FOO.read(scope);
+    assert_eq!((*a, *b), (2, 2));
+    assert!(FOO.try_read(scope).is_some());
+    assert!(FOO.try_write(scope).is_none());
+    drop((a, b));
+    assert!(FOO.try_write(scope).is_some());
+}
@@ -0,0 +1,86 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::thread::LocalKey;
+
+use serde::ser::{Error, Serialize, Serializer};
+
+use crate::{LocalKeyExt, StackToken};
+
+/// Adds [`serde`] support to [`LocalKey`]s holding [`RefCell`] values.
+///
+/// This is useful to dump the state of a thread local for diagnostics:
+///
+/// ```
+/// use stack_tokens::{stack_token, SerdeRefCellLocalKeyExt};
+/// use std::cell::RefCell;
+///
+/// thread_local! {
+///     static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]);
+/// }
+///
+/// stack_token!(scope);
+/// let json = serde_json::to_string(&FOO.serialize(scope)).unwrap();
+/// assert_eq!(json, "[1,2,3]");
+/// ```
+pub trait SerdeRefCellLocalKeyExt<T> {
+    /// Returns a wrapper that serializes the contained value.
+    ///
+    /// The [`RefCell`] is only borrowed while the value is serialized.  If
+    /// it is mutably borrowed at that time serialization fails with an
+    /// error.
+    fn serialize<'stack>(&'static self, token: &'stack StackToken) -> TlsSerialize<'stack, T>
+    where
+        T: Serialize;
+}
+
+impl<T: 'static> SerdeRefCellLocalKeyExt<T> for LocalKey<RefCell<T>> {
+    #[inline]
+    #[track_caller]
+    fn serialize<'stack>(&'static self, token: &'stack StackToken) -> TlsSerialize<'stack, T>
+    where
+        T: Serialize,
+    {
+        TlsSerialize {
+            cell: self.borrow(token),
+        }
+    }
+}
+
+/// Serializes the value of a [`RefCell`] thread local.
+///
+/// This is returned by [`SerdeRefCellLocalKeyExt::serialize`].
+pub struct TlsSerialize<'stack, T> {
+    cell: &'stack RefCell<T>,
+}
+
+impl<'stack, T: Serialize> Serialize for TlsSerialize<'stack, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.cell
+            .try_borrow()
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+impl<'stack, T: fmt::Debug> fmt::Debug for TlsSerialize<'stack, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TlsSerialize").field(self.cell).finish()
+    }
+}
+
+#[test]
+fn test_serde_ref_cell() {
+    use crate::{stack_token, RefCellLocalKeyExt};
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = const { RefCell::new(Vec::new()) }; }
+
+    stack_token!(scope);
+    FOO.as_mut(scope).extend([1, -2, 3]);
+    assert_eq!(
+        serde_json::to_string(&FOO.serialize(scope)).unwrap(),
+        "[1,-2,3]"
+    );
+
+    let _guard = FOO.as_mut(scope);
+    assert!(serde_json::to_string(&FOO.serialize(scope)).is_err());
+}
@@ -0,0 +1,92 @@
+use thread_local::ThreadLocal;
+
+use crate::{StackBorrow, StackToken};
+
+/// Adds [`StackToken`] support to [`ThreadLocal`] from the `thread_local`
+/// crate.
+///
+/// Unlike a [`LocalKey`](std::thread::LocalKey) a [`ThreadLocal`] is a
+/// regular value and the borrows it hands out are already bound to its
+/// lifetime.  This trait additionally binds them to the token so that code
+/// built on stack tokens works the same for both kinds of thread locals.
+pub trait ThreadLocalExt<T: Send> {
+    /// Borrows the value for the current thread with a [`StackToken`],
+    /// initializing it with `Default::default()` if needed.
+    fn borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T
+    where
+        T: Default;
+
+    /// Borrows the value for the current thread with a [`StackToken`],
+    /// initializing it with `f` if needed.
+    fn borrow_or<'stack, F: FnOnce() -> T>(
+        &'stack self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack T;
+
+    /// Borrows the value for the current thread with a [`StackToken`] if it
+    /// was initialized.
+    fn try_borrow<'stack>(&'stack self, token: &'stack StackToken) -> Option<&'stack T>;
+}
+
+impl<T: Send> ThreadLocalExt<T> for ThreadLocal<T> {
+    #[inline]
+    #[track_caller]
+    fn borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T
+    where
+        T: Default,
+    {
+        let _ = token;
+        self.get_or_default()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn borrow_or<'stack, F: FnOnce() -> T>(
+        &'stack self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack T {
+        let _ = token;
+        self.get_or(f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_borrow<'stack>(&'stack self, token: &'stack StackToken) -> Option<&'stack T> {
+        let _ = token;
+        self.get()
+    }
+}
+
+impl<T: Send + Default> StackBorrow for ThreadLocal<T> {
+    type Target = T;
+
+    #[inline]
+    #[track_caller]
+    fn stack_borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T {
+        self.borrow(token)
+    }
+}
+
+#[test]
+fn test_thread_local_crate() {
+    use crate::stack_token;
+    use std::cell::Cell;
+
+    static FOO: ThreadLocal<Cell<u32>> = ThreadLocal::new();
+
+    stack_token!(scope);
+    assert!(FOO.try_borrow(scope).is_none());
+    FOO.borrow(scope).set(23);
+    assert_eq!(FOO.borrow_or(scope, || Cell::new(42)).get(), 23);
+    assert_eq!(FOO.stack_borrow(scope).get(), 23);
+
+    std::thread::spawn(|| {
+        stack_token!(scope);
+        assert_eq!(FOO.borrow_or(scope, || Cell::new(42)).get(), 42);
+    })
+    .join()
+    .unwrap();
+    assert_eq!(FOO.try_borrow(scope).map(Cell::get), Some(23));
+}
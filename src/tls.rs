@@ -0,0 +1,3918 @@
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::{
+    BorrowError, BorrowMutError, Cell, LazyCell, OnceCell, Ref, RefCell, RefMut, UnsafeCell,
+};
+use std::collections::{btree_map, BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::mem::{self, transmute};
+use std::ops::{Deref, RangeBounds};
+use std::pin::Pin;
+use std::ptr::{self, NonNull};
+use std::rc::{Rc, Weak};
+use std::slice;
+use std::sync::atomic::{
+    AtomicBool, AtomicI16, AtomicI32, AtomicI64, AtomicI8, AtomicIsize, AtomicPtr, AtomicU16,
+    AtomicU32, AtomicU64, AtomicU8, AtomicUsize, Ordering,
+};
+use std::sync::{
+    Arc, LazyLock, LockResult, Mutex, MutexGuard, OnceLock, PoisonError, RwLock, RwLockReadGuard,
+    RwLockWriteGuard, TryLockResult,
+};
+use std::thread::{self, AccessError, LocalKey};
+
+use crate::{StackBorrow, StackRef, StackToken};
+
+/// Adds [`StackToken`] support to the standard library's [`LocalKey`].
+pub trait LocalKeyExt<T> {
+    /// Borrows the value from the TLS with a [`StackToken`].
+    ///
+    /// This goes through [`LocalKey::with`] and is inlined into the same
+    /// code as calling it with a closure.  See `benches/borrow.rs`.
+    fn borrow<'stack>(&'static self, token: &'stack StackToken) -> &'stack T;
+
+    /// Borrows the value from the TLS with a [`StackToken`] if it is still
+    /// available.
+    ///
+    /// Unlike [`borrow`](Self::borrow) this does not panic if the thread local
+    /// has already been destroyed or is currently being destroyed, which can
+    /// happen if it is accessed from the destructors of other thread locals
+    /// during thread shutdown.  Instead an [`AccessError`] is returned.
+    fn try_borrow<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Result<&'stack T, AccessError>;
+
+    /// Borrows the value from the TLS with a [`StackToken`] and returns it
+    /// wrapped in a [`TlsGuard`].
+    fn guard<'stack>(&'static self, token: &'stack StackToken) -> TlsGuard<'stack, T>;
+
+    /// Borrows a component of the value from the TLS with a [`StackToken`].
+    ///
+    /// This is useful to only hand out a reference to a field of the value.
+    fn borrow_map<'stack, U: ?Sized, F: FnOnce(&T) -> &U>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack U;
+
+    /// Returns a copy of the value from the TLS.
+    ///
+    /// The returned value is not bound to the lifetime of the token.
+    fn get_copy(&'static self, token: &StackToken) -> T
+    where
+        T: Copy;
+
+    /// Returns a non-null pointer to the value for passing it to foreign
+    /// code.
+    ///
+    /// The pointer is only valid for as long as the token is alive and the
+    /// thread has not started to tear down its thread locals.  It must not
+    /// be written through.
+    fn as_non_null(&'static self, token: &StackToken) -> NonNull<T>;
+
+    /// Returns a wrapper that formats the value with [`Display`](fmt::Display).
+    ///
+    /// This is useful for passing thread locals to logging macros.  For a
+    /// [`RefCell`] thread local use
+    /// [`RefCellLocalKeyExt::display_ref`] instead.
+    fn display<'stack>(&'static self, token: &'stack StackToken) -> TlsDisplay<'stack, T>
+    where
+        T: fmt::Display;
+
+    /// Returns a wrapper that formats the value with [`Debug`](fmt::Debug).
+    ///
+    /// For a [`RefCell`] thread local this formats the [`RefCell`] itself,
+    /// [`RefCellLocalKeyExt::debug_ref`] formats the contained value.
+    fn debug<'stack>(&'static self, token: &'stack StackToken) -> TlsDebug<'stack, T>
+    where
+        T: fmt::Debug;
+}
+
+impl<T: 'static> LocalKeyExt<T> for LocalKey<T> {
+    #[inline]
+    #[track_caller]
+    fn borrow<'stack>(&'static self, token: &'stack StackToken) -> &'stack T {
+        self.stack_borrow(token)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_borrow<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Result<&'stack T, AccessError> {
+        self.try_with(|value| unsafe { bind_to_token(value, token) })
+    }
+
+    #[inline]
+    #[track_caller]
+    fn guard<'stack>(&'static self, token: &'stack StackToken) -> TlsGuard<'stack, T> {
+        TlsGuard {
+            value: self.borrow(token),
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn borrow_map<'stack, U: ?Sized, F: FnOnce(&T) -> &U>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack U {
+        f(self.borrow(token))
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_copy(&'static self, token: &StackToken) -> T
+    where
+        T: Copy,
+    {
+        *self.borrow(token)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_non_null(&'static self, token: &StackToken) -> NonNull<T> {
+        NonNull::from(self.borrow(token))
+    }
+
+    #[inline]
+    #[track_caller]
+    fn display<'stack>(&'static self, token: &'stack StackToken) -> TlsDisplay<'stack, T>
+    where
+        T: fmt::Display,
+    {
+        TlsDisplay {
+            value: self.borrow(token),
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn debug<'stack>(&'static self, token: &'stack StackToken) -> TlsDebug<'stack, T>
+    where
+        T: fmt::Debug,
+    {
+        TlsDebug {
+            value: self.borrow(token),
+        }
+    }
+}
+
+/// A borrow of a thread local that dereferences to its value.
+///
+/// This is returned by [`LocalKeyExt::guard`] and allows methods to be
+/// called on the value directly.
+#[derive(Clone, Copy)]
+pub struct TlsGuard<'stack, T> {
+    value: &'stack T,
+}
+
+impl<'stack, T> TlsGuard<'stack, T> {
+    /// Returns the wrapped reference.
+    #[inline]
+    pub fn get(self) -> &'stack T {
+        self.value
+    }
+}
+
+impl<'stack, T> Deref for TlsGuard<'stack, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'stack, T> AsRef<T> for TlsGuard<'stack, T> {
+    #[inline]
+    fn as_ref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<'stack, T: fmt::Debug> fmt::Debug for TlsGuard<'stack, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.value, f)
+    }
+}
+
+/// Formats a thread local with [`Display`](fmt::Display).
+///
+/// This is returned by [`LocalKeyExt::display`].
+#[derive(Clone, Copy)]
+pub struct TlsDisplay<'stack, T> {
+    value: &'stack T,
+}
+
+impl<'stack, T: fmt::Display> fmt::Display for TlsDisplay<'stack, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self.value, f)
+    }
+}
+
+/// Formats a thread local with [`Debug`](fmt::Debug).
+///
+/// This is returned by [`LocalKeyExt::debug`].
+#[derive(Clone, Copy)]
+pub struct TlsDebug<'stack, T> {
+    value: &'stack T,
+}
+
+impl<'stack, T: fmt::Debug> fmt::Debug for TlsDebug<'stack, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.value, f)
+    }
+}
+
+impl<T: 'static> StackBorrow for LocalKey<T> {
+    type Target = T;
+
+    #[inline]
+    #[track_caller]
+    fn stack_borrow<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T {
+        // `LocalKey` is a handle to storage owned by the current thread: the
+        // value handed to `with` lives in that per-thread slot and not in the
+        // key, and every key emitted by `thread_local!` (a `const` item, which
+        // can also be moved into a local) refers to the same slot.  `with`
+        // asks for `&'static self` but only uses the key for the duration of
+        // the call, during which `self` is alive.  The value itself is owned
+        // by the current thread, as required by `bind_to_token`.
+        let key = unsafe { transmute::<&LocalKey<T>, &'static LocalKey<T>>(self) };
+        key.with(|value| unsafe { bind_to_token(value, token) })
+    }
+}
+
+impl<T: 'static> StackRef for LocalKey<T> {
+    type Target = T;
+
+    #[inline]
+    #[track_caller]
+    fn stack_ref<'stack>(&'stack self, token: &'stack StackToken) -> &'stack T {
+        self.stack_borrow(token)
+    }
+}
+
+/// Binds a reference into a thread local to the lifetime of a token.
+///
+/// This is the one place where references handed out by [`LocalKey`] get
+/// their lifetime extended.  Every borrow path of [`LocalKeyExt`] goes
+/// through this function and all other extension traits are built on top of
+/// [`LocalKeyExt`].
+/// The integrations for thread locals of other crates go through here too.
+///
+/// # Safety
+///
+/// The value must be owned by a thread local of the current thread.  As
+/// tokens cannot leave the thread or outlive the stack frame they were
+/// created in, the thread local is then alive for at least `'stack`.
+#[inline]
+#[track_caller]
+pub(crate) unsafe fn bind_to_token<'stack, T: ?Sized>(
+    value: &T,
+    token: &'stack StackToken,
+) -> &'stack T {
+    let _ = token;
+    #[cfg(all(feature = "track-tokens", debug_assertions))]
+    crate::__private::check_untracked_borrow();
+    transmute::<&T, &'stack T>(value)
+}
+
+/// Borrows the values of two thread locals with a [`StackToken`].
+///
+/// This is a shortcut for calling [`borrow`](LocalKeyExt::borrow) on both
+/// keys.  As the returned references are shared, both keys may refer to the
+/// same thread local.
+#[inline]
+#[track_caller]
+pub fn borrow2<'stack, A: 'static, B: 'static>(
+    a: &'static LocalKey<A>,
+    b: &'static LocalKey<B>,
+    token: &'stack StackToken,
+) -> (&'stack A, &'stack B) {
+    (a.borrow(token), b.borrow(token))
+}
+
+/// Borrows the values of three thread locals with a [`StackToken`].
+///
+/// See [`borrow2`] for more information.
+#[inline]
+#[track_caller]
+pub fn borrow3<'stack, A: 'static, B: 'static, C: 'static>(
+    a: &'static LocalKey<A>,
+    b: &'static LocalKey<B>,
+    c: &'static LocalKey<C>,
+    token: &'stack StackToken,
+) -> (&'stack A, &'stack B, &'stack C) {
+    (a.borrow(token), b.borrow(token), c.borrow(token))
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`RefCell`] values.
+///
+/// This extension traits provides the two methods [`as_ref`](Self::as_ref)
+/// and [`as_mut`](Self::as_mut) that let you directly borrow into the
+/// contained [`RefCell`] with a [`StackToken`].
+///
+/// The standard library already provides `set`, `take` and `replace` as
+/// inherent methods on [`LocalKey`]s holding [`RefCell`]s.  Inherent methods
+/// always win over trait methods, so the token based versions of `take` and
+/// `replace` are called [`take_value`](Self::take_value) and
+/// [`replace_value`](Self::replace_value) instead.
+pub trait RefCellLocalKeyExt<T> {
+    /// Acquires a reference to the contained value.
+    ///
+    /// Shared borrows can be nested freely, also from functions that are
+    /// called while an outer borrow is held.  This only panics if the value
+    /// is currently mutably borrowed.
+    fn as_ref<'stack>(&'static self, token: &'stack StackToken) -> Ref<'stack, T>;
+
+    /// Acquires a mutable reference to the contained value.
+    fn as_mut<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, T>;
+
+    /// Acquires a mutable reference to a default initialized value.
+    ///
+    /// A [`RefCell`] thread local is always initialized, so this is the same
+    /// as [`as_mut`](Self::as_mut).  It exists for generic code that wants
+    /// the same accessor as
+    /// [`OptionRefCellLocalKeyExt::get_or_default`].
+    fn get_or_default_mut<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, T>
+    where
+        T: Default;
+
+    /// Tries to acquire a reference to the contained value.
+    ///
+    /// Unlike [`as_ref`](Self::as_ref) this returns an error rather than
+    /// panicking if the value is currently mutably borrowed.
+    fn try_as_ref<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Result<Ref<'stack, T>, BorrowError>;
+
+    /// Tries to acquire a mutable reference to the contained value.
+    ///
+    /// Unlike [`as_mut`](Self::as_mut) this returns an error rather than
+    /// panicking if the value is currently borrowed.
+    fn try_as_mut<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Result<RefMut<'stack, T>, BorrowMutError>;
+
+    /// Acquires a reference to a component of the contained value.
+    ///
+    /// This is the equivalent of [`Ref::map`].
+    fn ref_map<'stack, U: ?Sized, F: FnOnce(&T) -> &U>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> Ref<'stack, U>;
+
+    /// Acquires a mutable reference to a component of the contained value.
+    ///
+    /// This is the equivalent of [`RefMut::map`].
+    fn ref_mut_map<'stack, U: ?Sized, F: FnOnce(&mut T) -> &mut U>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> RefMut<'stack, U>;
+
+    /// Acquires a reference to the contained value if `pred` holds.
+    ///
+    /// If the predicate returns `false` the borrow is released again and
+    /// `None` is returned.
+    fn as_ref_if<'stack, F: FnOnce(&T) -> bool>(
+        &'static self,
+        token: &'stack StackToken,
+        pred: F,
+    ) -> Option<Ref<'stack, T>>;
+
+    /// Takes the contained value, leaving `Default::default()` in its place.
+    fn take_value(&'static self, token: &StackToken) -> T
+    where
+        T: Default;
+
+    /// Replaces the contained value with `value` and returns the old value.
+    fn replace_value(&'static self, token: &StackToken, value: T) -> T;
+
+    /// Replaces the contained value with a new one computed from `f` and
+    /// returns the old value.
+    fn replace_with<F: FnOnce(&mut T) -> T>(&'static self, token: &StackToken, f: F) -> T;
+
+    /// Replaces the contained value with `value` and returns a mutable
+    /// borrow of the new value.
+    ///
+    /// The old value is dropped.  This is the same as calling
+    /// [`LocalKey::replace`] followed by [`as_mut`](Self::as_mut).
+    fn replace_and_borrow<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        value: T,
+    ) -> RefMut<'stack, T>;
+
+    /// Returns a clone of the contained value.
+    ///
+    /// The returned value is not bound to the lifetime of the token.
+    fn cloned(&'static self, token: &StackToken) -> T
+    where
+        T: Clone;
+
+    /// Takes a snapshot of the contained value for a later
+    /// [`restore`](Self::restore).
+    ///
+    /// This is the same as [`cloned`](Self::cloned).
+    fn snapshot(&'static self, token: &StackToken) -> T
+    where
+        T: Clone;
+
+    /// Restores the contained value from a snapshot, dropping the current
+    /// value.
+    fn restore(&'static self, token: &StackToken, snapshot: T);
+
+    /// Invokes `f` with a mutable reference to the contained value and
+    /// returns its result.
+    ///
+    /// The value is only borrowed for as long as `f` runs.
+    fn update<R, F: FnOnce(&mut T) -> R>(&'static self, token: &StackToken, f: F) -> R;
+
+    /// Temporarily overrides the contained value while `f` runs.
+    ///
+    /// The value is replaced with `value` and the old value is restored
+    /// once `f` returns, even if it panics.  The borrow of the [`RefCell`] is
+    /// released while `f` runs so the thread local can be accessed normally.
+    ///
+    /// `f` is handed a fresh token that only lives for the duration of the
+    /// call, so borrows made through it cannot be returned from `f`.  This
+    /// does not hold for borrows made through a token from outside of `f`.
+    ///
+    /// # Panics
+    ///
+    /// This panics if the value is still borrowed when `f` returns, for
+    /// instance because `f` returned a borrow made with an outer token, as
+    /// the old value could not be restored otherwise.
+    fn with_override<R, F: FnOnce(&StackToken) -> R>(
+        &'static self,
+        token: &StackToken,
+        value: T,
+        f: F,
+    ) -> R;
+
+    /// Replaces the contained value and returns a guard that restores the
+    /// old value when dropped.
+    ///
+    /// This is the guard based version of
+    /// [`with_override`](Self::with_override).  The old value is also
+    /// restored if the scope is left by a panic.
+    ///
+    /// # Panics
+    ///
+    /// Dropping the guard panics if the value is still borrowed at that
+    /// point.  If the guard is dropped while unwinding from another panic
+    /// the old value is dropped instead, as panicking again would abort.
+    fn set_scoped<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        value: T,
+    ) -> ResetGuard<'stack, T>;
+
+    /// Splits a mutable borrow of the contained value into two borrows of
+    /// different components.
+    ///
+    /// This is the equivalent of [`RefMut::map_split`].
+    fn as_mut_split<'stack, U: ?Sized, V: ?Sized, F: FnOnce(&mut T) -> (&mut U, &mut V)>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> (RefMut<'stack, U>, RefMut<'stack, V>);
+
+    /// Acquires a plain reference to the contained value by leaking the
+    /// borrow.
+    ///
+    /// A [`Ref`] returned by [`as_ref`](Self::as_ref) only derefs to a
+    /// reference bound to the guard, not to `'stack`.  This instead forgets
+    /// the guard so that the [`RefCell`] stays immutably borrowed for as long
+    /// as the thread local lives, the same way the unstable `Ref::leak`
+    /// does.
+    ///
+    /// **Any later attempt to borrow the value mutably on this thread will
+    /// fail**, including from other stack frames.  Only use this for values
+    /// that are never mutated again.
+    fn leak_ref<'stack>(&'static self, token: &'stack StackToken) -> &'stack T;
+
+    /// Returns `true` if the contained value is currently borrowed, either
+    /// mutably or immutably.
+    ///
+    /// If this returns `false` [`as_mut`](Self::as_mut) will not panic.
+    fn is_borrowed(&'static self, token: &StackToken) -> bool;
+
+    /// Returns `true` if the contained value is currently mutably borrowed.
+    ///
+    /// If this returns `false` [`as_ref`](Self::as_ref) will not panic.
+    fn is_borrowed_mut(&'static self, token: &StackToken) -> bool;
+
+    /// Returns a non-null pointer to the contained value for passing it to
+    /// foreign code.
+    ///
+    /// This does not borrow the [`RefCell`], so the caller is responsible
+    /// for not accessing the value through the pointer while it is borrowed.
+    /// The pointer is only valid for as long as the token is alive.
+    fn as_non_null_mut(&'static self, token: &StackToken) -> NonNull<T>;
+
+    /// Returns a wrapper that formats the contained value with
+    /// [`Display`](fmt::Display).
+    ///
+    /// The [`RefCell`] is only borrowed while the value is formatted.  If it
+    /// is mutably borrowed at that time `<borrowed>` is written instead.
+    fn display_ref<'stack>(&'static self, token: &'stack StackToken) -> TlsRefDisplay<'stack, T>
+    where
+        T: fmt::Display;
+
+    /// Returns a wrapper that formats the contained value with
+    /// [`Debug`](fmt::Debug).
+    ///
+    /// Like [`display_ref`](Self::display_ref) this only borrows the
+    /// [`RefCell`] while the value is formatted.
+    fn debug_ref<'stack>(&'static self, token: &'stack StackToken) -> TlsRefDebug<'stack, T>
+    where
+        T: fmt::Debug;
+}
+
+impl<T: 'static> RefCellLocalKeyExt<T> for LocalKey<RefCell<T>> {
+    #[inline]
+    #[track_caller]
+    fn as_ref<'stack>(&'static self, token: &'stack StackToken) -> Ref<'stack, T> {
+        self.borrow(token).borrow()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_mut<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, T> {
+        self.borrow(token).borrow_mut()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_default_mut<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, T>
+    where
+        T: Default,
+    {
+        self.as_mut(token)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_as_ref<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Result<Ref<'stack, T>, BorrowError> {
+        self.borrow(token).try_borrow()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_as_mut<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Result<RefMut<'stack, T>, BorrowMutError> {
+        self.borrow(token).try_borrow_mut()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn ref_map<'stack, U: ?Sized, F: FnOnce(&T) -> &U>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> Ref<'stack, U> {
+        Ref::map(self.as_ref(token), f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn ref_mut_map<'stack, U: ?Sized, F: FnOnce(&mut T) -> &mut U>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> RefMut<'stack, U> {
+        RefMut::map(self.as_mut(token), f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_ref_if<'stack, F: FnOnce(&T) -> bool>(
+        &'static self,
+        token: &'stack StackToken,
+        pred: F,
+    ) -> Option<Ref<'stack, T>> {
+        Ref::filter_map(self.as_ref(token), |x| if pred(x) { Some(x) } else { None }).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn take_value(&'static self, token: &StackToken) -> T
+    where
+        T: Default,
+    {
+        self.borrow(token).take()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn replace_value(&'static self, token: &StackToken, value: T) -> T {
+        self.borrow(token).replace(value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn replace_with<F: FnOnce(&mut T) -> T>(&'static self, token: &StackToken, f: F) -> T {
+        self.borrow(token).replace_with(f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn replace_and_borrow<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        value: T,
+    ) -> RefMut<'stack, T> {
+        let mut rv = self.as_mut(token);
+        *rv = value;
+        rv
+    }
+
+    #[inline]
+    #[track_caller]
+    fn cloned(&'static self, token: &StackToken) -> T
+    where
+        T: Clone,
+    {
+        self.as_ref(token).clone()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn snapshot(&'static self, token: &StackToken) -> T
+    where
+        T: Clone,
+    {
+        self.cloned(token)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn restore(&'static self, token: &StackToken, snapshot: T) {
+        *self.as_mut(token) = snapshot;
+    }
+
+    #[inline]
+    #[track_caller]
+    fn update<R, F: FnOnce(&mut T) -> R>(&'static self, token: &StackToken, f: F) -> R {
+        f(&mut *self.as_mut(token))
+    }
+
+    #[inline]
+    #[track_caller]
+    fn with_override<R, F: FnOnce(&StackToken) -> R>(
+        &'static self,
+        token: &StackToken,
+        value: T,
+        f: F,
+    ) -> R {
+        let _guard = self.set_scoped(token, value);
+        crate::stack_token!(scope);
+        f(scope)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn set_scoped<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        value: T,
+    ) -> ResetGuard<'stack, T> {
+        let cell = self.borrow(token);
+        ResetGuard {
+            old: Some(cell.replace(value)),
+            cell,
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_mut_split<'stack, U: ?Sized, V: ?Sized, F: FnOnce(&mut T) -> (&mut U, &mut V)>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> (RefMut<'stack, U>, RefMut<'stack, V>) {
+        RefMut::map_split(self.as_mut(token), f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn leak_ref<'stack>(&'static self, token: &'stack StackToken) -> &'stack T {
+        let value = self.as_ref(token);
+        // the guard is forgotten below so the borrow flag never gets reset
+        // and no mutable borrow can be handed out while the reference lives.
+        let rv = unsafe { &*(&*value as *const T) };
+        mem::forget(value);
+        rv
+    }
+
+    #[inline]
+    #[track_caller]
+    fn is_borrowed(&'static self, token: &StackToken) -> bool {
+        self.try_as_mut(token).is_err()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn is_borrowed_mut(&'static self, token: &StackToken) -> bool {
+        self.try_as_ref(token).is_err()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_non_null_mut(&'static self, token: &StackToken) -> NonNull<T> {
+        // a `RefCell` is never at the null address
+        unsafe { NonNull::new_unchecked(self.borrow(token).as_ptr()) }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn display_ref<'stack>(&'static self, token: &'stack StackToken) -> TlsRefDisplay<'stack, T>
+    where
+        T: fmt::Display,
+    {
+        TlsRefDisplay {
+            cell: self.borrow(token),
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn debug_ref<'stack>(&'static self, token: &'stack StackToken) -> TlsRefDebug<'stack, T>
+    where
+        T: fmt::Debug,
+    {
+        TlsRefDebug {
+            cell: self.borrow(token),
+        }
+    }
+}
+
+/// Formats the value of a [`RefCell`] thread local with
+/// [`Display`](fmt::Display).
+///
+/// This is returned by [`RefCellLocalKeyExt::display_ref`].
+#[derive(Clone, Copy)]
+pub struct TlsRefDisplay<'stack, T> {
+    cell: &'stack RefCell<T>,
+}
+
+impl<'stack, T: fmt::Display> fmt::Display for TlsRefDisplay<'stack, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell.try_borrow() {
+            Ok(value) => fmt::Display::fmt(&*value, f),
+            Err(_) => f.write_str("<borrowed>"),
+        }
+    }
+}
+
+/// Formats the value of a [`RefCell`] thread local with [`Debug`](fmt::Debug).
+///
+/// This is returned by [`RefCellLocalKeyExt::debug_ref`].
+#[derive(Clone, Copy)]
+pub struct TlsRefDebug<'stack, T> {
+    cell: &'stack RefCell<T>,
+}
+
+impl<'stack, T: fmt::Debug> fmt::Debug for TlsRefDebug<'stack, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.cell.try_borrow() {
+            Ok(value) => fmt::Debug::fmt(&*value, f),
+            Err(_) => f.write_str("<borrowed>"),
+        }
+    }
+}
+
+/// Restores the previous value of a [`RefCell`] thread local when dropped.
+///
+/// This is returned by [`RefCellLocalKeyExt::set_scoped`].
+#[must_use = "the old value is restored right away if the guard is not held"]
+pub struct ResetGuard<'stack, T> {
+    cell: &'stack RefCell<T>,
+    old: Option<T>,
+}
+
+impl<'stack, T> Drop for ResetGuard<'stack, T> {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(old) = self.old.take() {
+            if thread::panicking() {
+                // panicking again while unwinding would abort the process
+                if let Ok(mut value) = self.cell.try_borrow_mut() {
+                    *value = old;
+                }
+            } else {
+                *self.cell.borrow_mut() = old;
+            }
+        }
+    }
+}
+
+impl<'stack, T: fmt::Debug> fmt::Debug for ResetGuard<'stack, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ResetGuard")
+            .field("old", &self.old)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Borrows two thread locals with two fresh, distinct tokens.
+///
+/// This declares a hidden token per thread local and binds the borrows to
+/// the given names for the rest of the enclosing scope:
+///
+/// ```
+/// use stack_tokens::borrow_pair;
+/// use std::cell::Cell;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+///
+/// thread_local! {
+///     static COUNTER: AtomicUsize = AtomicUsize::new(0);
+///     static LAST: Cell<i32> = Cell::new(0);
+/// }
+///
+/// borrow_pair!(let (counter, last) = (COUNTER, LAST));
+/// counter.fetch_add(1, Ordering::Relaxed);
+/// last.set(42);
+/// assert_eq!(counter.load(Ordering::Relaxed), 1);
+/// assert_eq!(last.get(), 42);
+/// ```
+///
+/// To borrow two thread locals with an existing token use [`borrow2`].
+#[macro_export]
+macro_rules! borrow_pair {
+    (let ($a:ident, $b:ident) = ($key_a:expr, $key_b:expr $(,)?)) => {
+        $crate::stack_token!(token_a, token_b);
+        let $a = $crate::LocalKeyExt::borrow(&$key_a, token_a);
+        let $b = $crate::LocalKeyExt::borrow(&$key_b, token_b);
+    };
+}
+
+/// Mutably borrows two fields of a struct in a [`RefCell`] thread local.
+///
+/// This expands to a call to
+/// [`as_mut_split`](RefCellLocalKeyExt::as_mut_split) and evaluates to a
+/// tuple of two [`RefMut`]s.  The borrow checker rejects borrowing the same
+/// field twice.
+///
+/// ```
+/// use stack_tokens::{split_borrow, stack_token, RefCellLocalKeyExt};
+/// use std::cell::RefCell;
+///
+/// #[derive(Default)]
+/// struct State {
+///     names: Vec<String>,
+///     count: usize,
+/// }
+///
+/// thread_local! {
+///     static STATE: RefCell<State> = RefCell::default();
+/// }
+///
+/// stack_token!(scope);
+/// let (mut names, mut count) = split_borrow!(STATE, scope => { names, count });
+/// names.push("Peter".into());
+/// *count += names.len();
+/// drop((names, count));
+/// assert_eq!(STATE.as_ref(scope).count, 1);
+/// ```
+///
+/// Borrowing the same field twice does not compile:
+///
+/// ```compile_fail
+/// # use stack_tokens::{split_borrow, stack_token};
+/// # use std::cell::RefCell;
+/// # #[derive(Default)]
+/// # struct State { count: usize }
+/// # thread_local! { static STATE: RefCell<State> = RefCell::default(); }
+/// stack_token!(scope);
+/// let (a, b) = split_borrow!(STATE, scope => { count, count });
+/// ```
+#[macro_export]
+macro_rules! split_borrow {
+    ($key:expr, $token:expr => { $a:ident, $b:ident $(,)? }) => {
+        $crate::RefCellLocalKeyExt::as_mut_split(&$key, $token, |value| {
+            (&mut value.$a, &mut value.$b)
+        })
+    };
+}
+
+/// Binds a borrow of a thread local for the duration of a block.
+///
+/// The borrow, typically a [`Ref`] or [`RefMut`], is dropped at the end of
+/// the block and the macro evaluates to the value of the block.  This makes
+/// it hard to accidentally hold on to a borrow across other accesses to the
+/// same thread local:
+///
+/// ```
+/// use stack_tokens::{stack_token, with_borrowed, RefCellLocalKeyExt};
+/// use std::cell::RefCell;
+///
+/// thread_local! {
+///     static NAMES: RefCell<Vec<String>> = RefCell::new(Vec::new());
+/// }
+///
+/// stack_token!(scope);
+/// let len = with_borrowed!(NAMES.as_mut(scope) => |mut names| {
+///     names.push("Peter".into());
+///     names.len()
+/// });
+/// // the mutable borrow was released, so borrowing again is fine
+/// assert_eq!(NAMES.as_ref(scope).len(), len);
+/// ```
+#[macro_export]
+macro_rules! with_borrowed {
+    ($borrow:expr => |$binding:pat_param| $body:expr) => {{
+        let $binding = $borrow;
+        $body
+    }};
+}
+
+/// Declares [`RefCell`] thread locals with token based accessor functions.
+///
+/// This works like [`thread_local!`] but in addition to the thread local it
+/// declares a module of the same name with `as_ref` and `as_mut` functions
+/// that forward to [`RefCellLocalKeyExt`].  The type has to be spelled as
+/// `RefCell<T>`.  As the module refers to the thread local through `super`,
+/// the macro has to be used at module level rather than in a function.
+///
+/// ```
+/// use stack_tokens::{stack_token, thread_local_with_tokens};
+/// use std::cell::RefCell;
+///
+/// thread_local_with_tokens! {
+///     static NAMES: RefCell<Vec<String>> = RefCell::new(Vec::new());
+/// }
+///
+/// fn main() {
+///     stack_token!(scope);
+///     NAMES::as_mut(scope).push("Peter".into());
+///     assert_eq!(NAMES::as_ref(scope).len(), 1);
+/// }
+/// ```
+#[macro_export]
+macro_rules! thread_local_with_tokens {
+    () => {};
+    ($(#[$attr:meta])* static $name:ident: RefCell<$t:ty> = $init:expr $(; $($rest:tt)*)?) => {
+        $crate::thread_local_with_tokens!(@item [$(#[$attr])*] [] [pub(super)] $name, $t, $init);
+        $crate::thread_local_with_tokens!($($($rest)*)?);
+    };
+    ($(#[$attr:meta])* $vis:vis static $name:ident: RefCell<$t:ty> = $init:expr $(; $($rest:tt)*)?) => {
+        $crate::thread_local_with_tokens!(@item [$(#[$attr])*] [$vis] [$vis] $name, $t, $init);
+        $crate::thread_local_with_tokens!($($($rest)*)?);
+    };
+    (@item [$($attr:tt)*] [$($vis:tt)*] [$($fn_vis:tt)*] $name:ident, $t:ty, $init:expr) => {
+        ::std::thread_local! {
+            $($attr)* $($vis)* static $name: ::std::cell::RefCell<$t> = $init;
+        }
+
+        #[allow(non_snake_case)]
+        $($vis)* mod $name {
+            use super::*;
+
+            /// Acquires a reference to the contained value.
+            #[inline]
+            #[track_caller]
+            $($fn_vis)* fn as_ref<'stack>(
+                token: &'stack $crate::StackToken,
+            ) -> ::std::cell::Ref<'stack, $t> {
+                $crate::RefCellLocalKeyExt::as_ref(&$name, token)
+            }
+
+            /// Acquires a mutable reference to the contained value.
+            #[inline]
+            #[track_caller]
+            $($fn_vis)* fn as_mut<'stack>(
+                token: &'stack $crate::StackToken,
+            ) -> ::std::cell::RefMut<'stack, $t> {
+                $crate::RefCellLocalKeyExt::as_mut(&$name, token)
+            }
+        }
+    };
+}
+
+/// Swaps the values of two [`RefCell`] thread locals.
+///
+/// If both keys refer to the same thread local this does nothing.
+///
+/// # Panics
+///
+/// Panics if the value in either [`RefCell`] is currently borrowed.
+#[inline]
+#[track_caller]
+pub fn swap<T: 'static>(
+    a: &'static LocalKey<RefCell<T>>,
+    b: &'static LocalKey<RefCell<T>>,
+    token: &StackToken,
+) {
+    let a = a.borrow(token);
+    let b = b.borrow(token);
+    if !ptr::eq(a, b) {
+        a.swap(b);
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with an [`Option`].
+///
+/// This is useful for thread locals that are only initialized at a later
+/// point.  The methods return `None` rather than panicking if the thread
+/// local does not hold a value.
+pub trait OptionRefCellLocalKeyExt<T> {
+    /// Acquires a reference to the contained value if there is one.
+    fn as_deref<'stack>(&'static self, token: &'stack StackToken) -> Option<Ref<'stack, T>>;
+
+    /// Acquires a reference to the contained value, or to `default` if
+    /// there is none.
+    ///
+    /// The [`RefCell`] is only borrowed if it holds a value.
+    fn as_ref_or<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        default: &'stack T,
+    ) -> RefOr<'stack, T>;
+
+    /// Acquires a mutable reference to the contained value if there is one.
+    fn as_deref_mut<'stack>(&'static self, token: &'stack StackToken) -> Option<RefMut<'stack, T>>;
+
+    /// Acquires a mutable reference to the contained value, initializing it
+    /// with `Default::default()` if there is none.
+    fn get_or_default<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, T>
+    where
+        T: Default;
+}
+
+impl<T: 'static> OptionRefCellLocalKeyExt<T> for LocalKey<RefCell<Option<T>>> {
+    #[inline]
+    #[track_caller]
+    fn as_deref<'stack>(&'static self, token: &'stack StackToken) -> Option<Ref<'stack, T>> {
+        Ref::filter_map(self.as_ref(token), |x| x.as_ref()).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_ref_or<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        default: &'stack T,
+    ) -> RefOr<'stack, T> {
+        match Ref::filter_map(self.as_ref(token), |x| x.as_ref()) {
+            Ok(value) => RefOr::Ref(value),
+            Err(_) => RefOr::Default(default),
+        }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_deref_mut<'stack>(&'static self, token: &'stack StackToken) -> Option<RefMut<'stack, T>> {
+        RefMut::filter_map(self.as_mut(token), |x| x.as_mut()).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_default<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, T>
+    where
+        T: Default,
+    {
+        RefMut::map(self.as_mut(token), |x| x.get_or_insert_with(T::default))
+    }
+}
+
+/// Either a [`Ref`] into a thread local or a fallback reference.
+///
+/// This is returned by [`OptionRefCellLocalKeyExt::as_ref_or`].
+#[derive(Debug)]
+pub enum RefOr<'stack, T> {
+    /// The value of the thread local.
+    Ref(Ref<'stack, T>),
+    /// The fallback value.
+    Default(&'stack T),
+}
+
+impl<'stack, T> Deref for RefOr<'stack, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        match self {
+            RefOr::Ref(value) => value,
+            RefOr::Default(value) => value,
+        }
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with an
+/// optional [`Box`].
+///
+/// This is useful for slots holding boxed trait objects as the borrows point
+/// to the boxed value directly:
+///
+/// ```
+/// use stack_tokens::{stack_token, OptionBoxRefCellLocalKeyExt, RefCellLocalKeyExt};
+/// use std::cell::RefCell;
+///
+/// trait Handler {
+///     fn handle(&self) -> &str;
+/// }
+///
+/// struct Hello;
+///
+/// impl Handler for Hello {
+///     fn handle(&self) -> &str {
+///         "Hello"
+///     }
+/// }
+///
+/// thread_local! {
+///     static HANDLER: RefCell<Option<Box<dyn Handler>>> = RefCell::new(None);
+/// }
+///
+/// stack_token!(scope);
+/// *HANDLER.as_mut(scope) = Some(Box::new(Hello));
+/// assert_eq!(HANDLER.peek(scope).unwrap().handle(), "Hello");
+/// ```
+pub trait OptionBoxRefCellLocalKeyExt<T: ?Sized> {
+    /// Acquires a reference to the boxed value if there is one.
+    fn peek<'stack>(&'static self, token: &'stack StackToken) -> Option<Ref<'stack, T>>;
+
+    /// Acquires a mutable reference to the boxed value if there is one.
+    fn peek_mut<'stack>(&'static self, token: &'stack StackToken) -> Option<RefMut<'stack, T>>;
+}
+
+impl<T: ?Sized + 'static> OptionBoxRefCellLocalKeyExt<T> for LocalKey<RefCell<Option<Box<T>>>> {
+    #[inline]
+    #[track_caller]
+    fn peek<'stack>(&'static self, token: &'stack StackToken) -> Option<Ref<'stack, T>> {
+        Ref::filter_map(self.as_ref(token), |x| x.as_deref()).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn peek_mut<'stack>(&'static self, token: &'stack StackToken) -> Option<RefMut<'stack, T>> {
+        RefMut::filter_map(self.as_mut(token), |x| x.as_deref_mut()).ok()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a
+/// boxed [`Any`].
+pub trait AnyRefCellLocalKeyExt {
+    /// Acquires a reference to the boxed value if it is of type `U`.
+    fn downcast_ref<'stack, U: 'static>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Option<Ref<'stack, U>>;
+}
+
+impl AnyRefCellLocalKeyExt for LocalKey<RefCell<Box<dyn Any>>> {
+    #[inline]
+    #[track_caller]
+    fn downcast_ref<'stack, U: 'static>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> Option<Ref<'stack, U>> {
+        Ref::filter_map(self.as_ref(token), |x| (**x).downcast_ref()).ok()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a [`Vec`].
+pub trait VecRefCellLocalKeyExt<T> {
+    /// Acquires a reference to the element at `index`.
+    ///
+    /// Returns `None` if the index is out of bounds.
+    fn get_ref<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        index: usize,
+    ) -> Option<Ref<'stack, T>>;
+
+    /// Acquires a mutable reference to the element at `index`.
+    ///
+    /// Returns `None` if the index is out of bounds.
+    fn get_mut<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        index: usize,
+    ) -> Option<RefMut<'stack, T>>;
+
+    /// Acquires a reference to a subslice of the vector.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is out of bounds, like slice indexing does.
+    fn slice<'stack, R: RangeBounds<usize>>(
+        &'static self,
+        token: &'stack StackToken,
+        range: R,
+    ) -> Ref<'stack, [T]>;
+
+    /// Acquires a reference to a subslice of the vector.
+    ///
+    /// Returns `None` if the range is out of bounds.
+    fn try_slice<'stack, R: RangeBounds<usize>>(
+        &'static self,
+        token: &'stack StackToken,
+        range: R,
+    ) -> Option<Ref<'stack, [T]>>;
+
+    /// Mutably borrows the vector as two disjoint slices split at `mid`.
+    ///
+    /// The first slice contains the elements before `mid`, the second one
+    /// the rest.  Both halves can be modified independently.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `mid > len`.
+    fn split_at_mut<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        mid: usize,
+    ) -> (RefMut<'stack, [T]>, RefMut<'stack, [T]>);
+
+    /// Appends an element to the back of the vector.
+    fn push(&'static self, token: &StackToken, value: T);
+
+    /// Removes the last element from the vector and returns it.
+    fn pop(&'static self, token: &StackToken) -> Option<T>;
+
+    /// Removes the element at `index` and returns it, replacing it with the
+    /// last element.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    fn swap_remove(&'static self, token: &StackToken, index: usize) -> T;
+
+    /// Retains only the elements for which `f` returns `true`.
+    ///
+    /// The vector is borrowed mutably while `f` runs, so `f` must not access
+    /// the same thread local.
+    fn retain<F: FnMut(&T) -> bool>(&'static self, token: &StackToken, f: F);
+
+    /// Appends all elements of an iterator to the back of the vector.
+    ///
+    /// The vector is borrowed mutably while the iterator is consumed, so the
+    /// iterator must not access the same thread local.
+    fn extend<I: IntoIterator<Item = T>>(&'static self, token: &StackToken, iter: I);
+
+    /// Moves all elements of `other` to the back of the vector, leaving
+    /// `other` empty.
+    fn append(&'static self, token: &StackToken, other: &mut Vec<T>);
+
+    /// Removes all elements from the vector.
+    fn clear(&'static self, token: &StackToken);
+
+    /// Takes all elements out of the vector, leaving it empty.
+    ///
+    /// The vector is only borrowed for the swap, so the returned elements
+    /// can be processed while the thread local is accessed again.
+    fn drain_all(&'static self, token: &StackToken) -> Vec<T>;
+
+    /// Returns the number of elements in the vector.
+    fn len(&'static self, token: &StackToken) -> usize;
+
+    /// Invokes `f` for each element of the vector.
+    ///
+    /// The vector is borrowed for as long as the iteration runs.
+    fn for_each<F: FnMut(&T)>(&'static self, token: &StackToken, f: F);
+
+    /// Invokes `f` with an iterator over the vector.
+    ///
+    /// The vector is borrowed for as long as `f` runs.
+    fn with_iter<R, F: FnOnce(slice::Iter<'_, T>) -> R>(
+        &'static self,
+        token: &StackToken,
+        f: F,
+    ) -> R;
+}
+
+impl<T: 'static> VecRefCellLocalKeyExt<T> for LocalKey<RefCell<Vec<T>>> {
+    #[inline]
+    #[track_caller]
+    fn get_ref<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        index: usize,
+    ) -> Option<Ref<'stack, T>> {
+        Ref::filter_map(self.as_ref(token), |x| x.get(index)).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_mut<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        index: usize,
+    ) -> Option<RefMut<'stack, T>> {
+        RefMut::filter_map(self.as_mut(token), |x| x.get_mut(index)).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn slice<'stack, R: RangeBounds<usize>>(
+        &'static self,
+        token: &'stack StackToken,
+        range: R,
+    ) -> Ref<'stack, [T]> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        Ref::map(self.as_ref(token), |x| &x[bounds])
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_slice<'stack, R: RangeBounds<usize>>(
+        &'static self,
+        token: &'stack StackToken,
+        range: R,
+    ) -> Option<Ref<'stack, [T]>> {
+        let bounds = (range.start_bound().cloned(), range.end_bound().cloned());
+        Ref::filter_map(self.as_ref(token), |x| x.get(bounds)).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn split_at_mut<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        mid: usize,
+    ) -> (RefMut<'stack, [T]>, RefMut<'stack, [T]>) {
+        let vec = self.as_mut(token);
+        assert!(mid <= vec.len(), "mid > len");
+        RefMut::map_split(vec, |x| x.split_at_mut(mid))
+    }
+
+    #[inline]
+    #[track_caller]
+    fn push(&'static self, token: &StackToken, value: T) {
+        self.as_mut(token).push(value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn pop(&'static self, token: &StackToken) -> Option<T> {
+        self.as_mut(token).pop()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn swap_remove(&'static self, token: &StackToken, index: usize) -> T {
+        self.as_mut(token).swap_remove(index)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn retain<F: FnMut(&T) -> bool>(&'static self, token: &StackToken, f: F) {
+        self.as_mut(token).retain(f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn extend<I: IntoIterator<Item = T>>(&'static self, token: &StackToken, iter: I) {
+        self.as_mut(token).extend(iter)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn append(&'static self, token: &StackToken, other: &mut Vec<T>) {
+        self.as_mut(token).append(other)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn clear(&'static self, token: &StackToken) {
+        self.as_mut(token).clear()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn drain_all(&'static self, token: &StackToken) -> Vec<T> {
+        mem::take(&mut *self.as_mut(token))
+    }
+
+    #[inline]
+    #[track_caller]
+    fn len(&'static self, token: &StackToken) -> usize {
+        self.as_ref(token).len()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn for_each<F: FnMut(&T)>(&'static self, token: &StackToken, f: F) {
+        self.as_ref(token).iter().for_each(f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn with_iter<R, F: FnOnce(slice::Iter<'_, T>) -> R>(
+        &'static self,
+        token: &StackToken,
+        f: F,
+    ) -> R {
+        f(self.as_ref(token).iter())
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with an array.
+pub trait ArrayRefCellLocalKeyExt<T, const N: usize> {
+    /// Acquires a reference to the element at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds, like array indexing does.
+    fn get<'stack>(&'static self, token: &'stack StackToken, index: usize) -> Ref<'stack, T>;
+
+    /// Acquires a mutable reference to the element at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds, like array indexing does.
+    fn get_mut<'stack>(&'static self, token: &'stack StackToken, index: usize)
+        -> RefMut<'stack, T>;
+}
+
+impl<T: 'static, const N: usize> ArrayRefCellLocalKeyExt<T, N> for LocalKey<RefCell<[T; N]>> {
+    #[inline]
+    #[track_caller]
+    fn get<'stack>(&'static self, token: &'stack StackToken, index: usize) -> Ref<'stack, T> {
+        Ref::map(self.as_ref(token), |x| &x[index])
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_mut<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+        index: usize,
+    ) -> RefMut<'stack, T> {
+        RefMut::map(self.as_mut(token), |x| &mut x[index])
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a [`HashMap`].
+pub trait HashMapRefCellLocalKeyExt<K, V> {
+    /// Inserts a key-value pair into the map, returning the old value.
+    fn insert(&'static self, token: &StackToken, key: K, value: V) -> Option<V>;
+
+    /// Acquires a reference to the value corresponding to the key.
+    fn get<'stack, Q>(&'static self, token: &'stack StackToken, key: &Q) -> Option<Ref<'stack, V>>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Returns a clone of the value corresponding to the key.
+    ///
+    /// The returned value is not bound to the lifetime of the token and the
+    /// map is only borrowed for the clone.
+    fn get_cloned<Q>(&'static self, token: &StackToken, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone;
+
+    /// Acquires a reference to the value corresponding to the key, inserting
+    /// the result of `f` first if the key is absent.
+    ///
+    /// The map is only borrowed mutably while `f` runs, so `f` must not
+    /// access the same thread local.  The returned reference holds a shared
+    /// borrow and other shared borrows of the map can be taken alongside it.
+    fn get_or_insert_with<'stack, F: FnOnce() -> V>(
+        &'static self,
+        token: &'stack StackToken,
+        key: K,
+        f: F,
+    ) -> Ref<'stack, V>
+    where
+        K: Clone;
+
+    /// Returns `true` if the map contains a value for the key.
+    fn contains_key<Q>(&'static self, token: &StackToken, key: &Q) -> bool
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Removes a key from the map, returning the value if it was present.
+    fn remove<Q>(&'static self, token: &StackToken, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Removes all key-value pairs from the map.
+    fn clear(&'static self, token: &StackToken);
+}
+
+impl<K, V, S> HashMapRefCellLocalKeyExt<K, V> for LocalKey<RefCell<HashMap<K, V, S>>>
+where
+    K: Hash + Eq + 'static,
+    V: 'static,
+    S: BuildHasher + 'static,
+{
+    #[inline]
+    #[track_caller]
+    fn insert(&'static self, token: &StackToken, key: K, value: V) -> Option<V> {
+        self.as_mut(token).insert(key, value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get<'stack, Q>(&'static self, token: &'stack StackToken, key: &Q) -> Option<Ref<'stack, V>>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        Ref::filter_map(self.as_ref(token), |x| x.get(key)).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_cloned<Q>(&'static self, token: &StackToken, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        self.as_ref(token).get(key).cloned()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_insert_with<'stack, F: FnOnce() -> V>(
+        &'static self,
+        token: &'stack StackToken,
+        key: K,
+        f: F,
+    ) -> Ref<'stack, V>
+    where
+        K: Clone,
+    {
+        self.as_mut(token).entry(key.clone()).or_insert_with(f);
+        Ref::map(self.as_ref(token), |map| &map[&key])
+    }
+
+    #[inline]
+    #[track_caller]
+    fn contains_key<Q>(&'static self, token: &StackToken, key: &Q) -> bool
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.as_ref(token).contains_key(key)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn remove<Q>(&'static self, token: &StackToken, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.as_mut(token).remove(key)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn clear(&'static self, token: &StackToken) {
+        self.as_mut(token).clear()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a [`HashSet`].
+pub trait HashSetRefCellLocalKeyExt<T> {
+    /// Adds a value to the set, returning `true` if it was newly inserted.
+    fn insert(&'static self, token: &StackToken, value: T) -> bool;
+
+    /// Returns `true` if the set contains the value.
+    fn contains<Q>(&'static self, token: &StackToken, value: &Q) -> bool
+    where
+        T: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Removes a value from the set, returning `true` if it was present.
+    fn remove<Q>(&'static self, token: &StackToken, value: &Q) -> bool
+    where
+        T: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized;
+
+    /// Removes all values from the set.
+    fn clear(&'static self, token: &StackToken);
+}
+
+impl<T, S> HashSetRefCellLocalKeyExt<T> for LocalKey<RefCell<HashSet<T, S>>>
+where
+    T: Hash + Eq + 'static,
+    S: BuildHasher + 'static,
+{
+    #[inline]
+    #[track_caller]
+    fn insert(&'static self, token: &StackToken, value: T) -> bool {
+        self.as_mut(token).insert(value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn contains<Q>(&'static self, token: &StackToken, value: &Q) -> bool
+    where
+        T: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.as_ref(token).contains(value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn remove<Q>(&'static self, token: &StackToken, value: &Q) -> bool
+    where
+        T: std::borrow::Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.as_mut(token).remove(value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn clear(&'static self, token: &StackToken) {
+        self.as_mut(token).clear()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a pinned [`Box`].
+///
+/// This gives pinned access to values such as self-referential generators
+/// stored in a thread local.
+pub trait PinLocalKeyExt<T: ?Sized> {
+    /// Acquires a pinned reference to the contained value.
+    fn as_pin_ref<'stack>(&'static self, token: &'stack StackToken) -> Pin<Ref<'stack, T>>;
+
+    /// Acquires a pinned mutable reference to the contained value.
+    fn as_pin_mut<'stack>(&'static self, token: &'stack StackToken) -> Pin<RefMut<'stack, T>>;
+}
+
+impl<T: ?Sized + 'static> PinLocalKeyExt<T> for LocalKey<RefCell<Pin<Box<T>>>> {
+    #[inline]
+    #[track_caller]
+    fn as_pin_ref<'stack>(&'static self, token: &'stack StackToken) -> Pin<Ref<'stack, T>> {
+        let value = Ref::map(self.as_ref(token), |x| x.as_ref().get_ref());
+        // the value is already pinned in the box.  As the box can only be
+        // reached through the pin the value can never be moved.
+        unsafe { Pin::new_unchecked(value) }
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_pin_mut<'stack>(&'static self, token: &'stack StackToken) -> Pin<RefMut<'stack, T>> {
+        let value = RefMut::map(self.as_mut(token), |x| unsafe {
+            x.as_mut().get_unchecked_mut()
+        });
+        // see above; the reference is only ever exposed behind a pin again.
+        unsafe { Pin::new_unchecked(value) }
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a [`VecDeque`].
+pub trait VecDequeRefCellLocalKeyExt<T> {
+    /// Appends an element to the back of the deque.
+    fn push_back(&'static self, token: &StackToken, value: T);
+
+    /// Removes the first element from the deque and returns it.
+    fn pop_front(&'static self, token: &StackToken) -> Option<T>;
+
+    /// Returns the number of elements in the deque.
+    fn len(&'static self, token: &StackToken) -> usize;
+
+    /// Removes all elements from the deque.
+    fn clear(&'static self, token: &StackToken);
+
+    /// Rearranges the deque so that its elements are contiguous and
+    /// acquires a mutable reference to them as a slice.
+    ///
+    /// This is the equivalent of [`VecDeque::make_contiguous`].
+    fn as_contiguous_mut<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, [T]>;
+}
+
+impl<T: 'static> VecDequeRefCellLocalKeyExt<T> for LocalKey<RefCell<VecDeque<T>>> {
+    #[inline]
+    #[track_caller]
+    fn push_back(&'static self, token: &StackToken, value: T) {
+        self.as_mut(token).push_back(value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn pop_front(&'static self, token: &StackToken) -> Option<T> {
+        self.as_mut(token).pop_front()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn len(&'static self, token: &StackToken) -> usize {
+        self.as_ref(token).len()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn clear(&'static self, token: &StackToken) {
+        self.as_mut(token).clear()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_contiguous_mut<'stack>(&'static self, token: &'stack StackToken) -> RefMut<'stack, [T]> {
+        RefMut::map(self.as_mut(token), |x| x.make_contiguous())
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a [`BTreeMap`].
+pub trait BTreeMapRefCellLocalKeyExt<K, V> {
+    /// Inserts a key-value pair into the map, returning the old value.
+    fn insert(&'static self, token: &StackToken, key: K, value: V) -> Option<V>;
+
+    /// Acquires a reference to the value corresponding to the key.
+    fn get<'stack, Q>(&'static self, token: &'stack StackToken, key: &Q) -> Option<Ref<'stack, V>>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Ord + ?Sized;
+
+    /// Removes a key from the map, returning the value if it was present.
+    fn remove<Q>(&'static self, token: &StackToken, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Ord + ?Sized;
+
+    /// Invokes `f` with an iterator over a sub-range of the map.
+    ///
+    /// The map is borrowed for as long as `f` runs.
+    fn with_range<Q, B, R, F>(&'static self, token: &StackToken, range: B, f: F) -> R
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Ord + ?Sized,
+        B: RangeBounds<Q>,
+        F: FnOnce(btree_map::Range<'_, K, V>) -> R;
+}
+
+impl<K: Ord + 'static, V: 'static> BTreeMapRefCellLocalKeyExt<K, V>
+    for LocalKey<RefCell<BTreeMap<K, V>>>
+{
+    #[inline]
+    #[track_caller]
+    fn insert(&'static self, token: &StackToken, key: K, value: V) -> Option<V> {
+        self.as_mut(token).insert(key, value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get<'stack, Q>(&'static self, token: &'stack StackToken, key: &Q) -> Option<Ref<'stack, V>>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        Ref::filter_map(self.as_ref(token), |x| x.get(key)).ok()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn remove<Q>(&'static self, token: &StackToken, key: &Q) -> Option<V>
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Ord + ?Sized,
+    {
+        self.as_mut(token).remove(key)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn with_range<Q, B, R, F>(&'static self, token: &StackToken, range: B, f: F) -> R
+    where
+        K: std::borrow::Borrow<Q>,
+        Q: Ord + ?Sized,
+        B: RangeBounds<Q>,
+        F: FnOnce(btree_map::Range<'_, K, V>) -> R,
+    {
+        f(self.as_ref(token).range(range))
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a [`String`].
+pub trait StringRefCellLocalKeyExt {
+    /// Invokes `f` with the string as a [`Cow::Borrowed`].
+    ///
+    /// The string is borrowed for as long as `f` runs.  A borrowed [`Cow`]
+    /// cannot be returned directly as nothing would keep the [`RefCell`]
+    /// borrowed while it is in use.
+    fn with_cow<R, F: FnOnce(Cow<'_, str>) -> R>(&'static self, token: &StackToken, f: F) -> R;
+}
+
+impl StringRefCellLocalKeyExt for LocalKey<RefCell<String>> {
+    #[inline]
+    #[track_caller]
+    fn with_cow<R, F: FnOnce(Cow<'_, str>) -> R>(&'static self, token: &StackToken, f: F) -> R {
+        f(Cow::Borrowed(self.as_ref(token).as_str()))
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`Cell`] values.
+///
+/// The standard library already provides `get`, `set`, `replace` and `take`
+/// as inherent methods on [`LocalKey`]s holding [`Cell`]s.  Inherent methods
+/// always win over trait methods, so the token based versions of these are
+/// called `get_value`, `set_value`, `replace_value` and `take_value` instead.
+pub trait CellLocalKeyExt<T> {
+    /// Returns a copy of the contained value.
+    fn get_value(&'static self, token: &StackToken) -> T
+    where
+        T: Copy;
+
+    /// Sets the contained value.
+    fn set_value(&'static self, token: &StackToken, value: T);
+
+    /// Replaces the contained value with `value` and returns the old value.
+    fn replace_value(&'static self, token: &StackToken, value: T) -> T;
+
+    /// Takes the contained value, leaving `Default::default()` in its place.
+    fn take_value(&'static self, token: &StackToken) -> T
+    where
+        T: Default;
+
+    /// Updates the contained value with the result of `f`.
+    ///
+    /// This is not called `update` as that would clash with `Cell::update`
+    /// and the inherent `update` method of `LocalKey<Cell<T>>`, which take
+    /// precedence over trait methods and would be called instead.
+    fn update_with<F: FnOnce(T) -> T>(&'static self, token: &StackToken, f: F)
+    where
+        T: Copy;
+
+    /// Swaps the values of two [`Cell`] thread locals.
+    ///
+    /// If both keys refer to the same thread local this does nothing.
+    fn swap(&'static self, token: &StackToken, other: &'static LocalKey<Cell<T>>);
+}
+
+impl<T: 'static> CellLocalKeyExt<T> for LocalKey<Cell<T>> {
+    #[inline]
+    #[track_caller]
+    fn get_value(&'static self, token: &StackToken) -> T
+    where
+        T: Copy,
+    {
+        self.borrow(token).get()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn set_value(&'static self, token: &StackToken, value: T) {
+        self.borrow(token).set(value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn replace_value(&'static self, token: &StackToken, value: T) -> T {
+        self.borrow(token).replace(value)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn take_value(&'static self, token: &StackToken) -> T
+    where
+        T: Default,
+    {
+        self.borrow(token).take()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn update_with<F: FnOnce(T) -> T>(&'static self, token: &StackToken, f: F)
+    where
+        T: Copy,
+    {
+        let cell = self.borrow(token);
+        cell.set(f(cell.get()));
+    }
+
+    #[inline]
+    #[track_caller]
+    fn swap(&'static self, token: &StackToken, other: &'static LocalKey<Cell<T>>) {
+        self.borrow(token).swap(other.borrow(token))
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`UnsafeCell`] values.
+///
+/// This is intended for code that implements its own synchronization on top
+/// of a thread local.
+pub trait UnsafeCellLocalKeyExt<T> {
+    /// Returns a raw pointer to the contained value.
+    ///
+    /// Obtaining the pointer is safe, dereferencing it is not.  The pointer
+    /// is only valid for as long as the token is alive and the caller is
+    /// responsible for upholding Rust's aliasing rules when reading from or
+    /// writing through it.
+    fn as_ptr(&'static self, token: &StackToken) -> *mut T;
+
+    /// Returns the pointer of [`as_ptr`](Self::as_ptr) as a [`NonNull`].
+    fn as_non_null_mut(&'static self, token: &StackToken) -> NonNull<T>;
+}
+
+impl<T: 'static> UnsafeCellLocalKeyExt<T> for LocalKey<UnsafeCell<T>> {
+    #[inline]
+    #[track_caller]
+    fn as_ptr(&'static self, token: &StackToken) -> *mut T {
+        self.borrow(token).get()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn as_non_null_mut(&'static self, token: &StackToken) -> NonNull<T> {
+        // an `UnsafeCell` is never at the null address
+        unsafe { NonNull::new_unchecked(self.as_ptr(token)) }
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`Mutex`] values.
+///
+/// This lets you lock the contained [`Mutex`] with a [`StackToken`] and get
+/// back a guard that is bound to the lifetime of the token.
+///
+/// Although the mutex is only ever reached from one thread, there is no way
+/// to skip the locking: a `&mut T` handed out without a guard could alias
+/// another one obtained through a nested call on the same thread.  As the
+/// lock is never contended, locking is only a single atomic operation.
+pub trait MutexLocalKeyExt<T> {
+    /// Acquires the mutex, blocking the current thread until it is able to do so.
+    fn lock<'stack>(&'static self, token: &'stack StackToken) -> LockResult<MutexGuard<'stack, T>>;
+
+    /// Attempts to acquire the mutex without blocking.
+    fn try_lock<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<MutexGuard<'stack, T>>;
+
+    /// Takes the value out of the mutex, leaving `Default::default()` in
+    /// its place.
+    ///
+    /// The value is taken even if the mutex is poisoned.  Like
+    /// [`lock`](Self::lock) this must not be called while the current
+    /// thread holds the lock.
+    fn take(&'static self, token: &StackToken) -> T
+    where
+        T: Default;
+}
+
+impl<T: 'static> MutexLocalKeyExt<T> for LocalKey<Mutex<T>> {
+    #[inline]
+    #[track_caller]
+    fn lock<'stack>(&'static self, token: &'stack StackToken) -> LockResult<MutexGuard<'stack, T>> {
+        self.borrow(token).lock()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_lock<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<MutexGuard<'stack, T>> {
+        self.borrow(token).try_lock()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn take(&'static self, token: &StackToken) -> T
+    where
+        T: Default,
+    {
+        let mut guard = self.lock(token).unwrap_or_else(PoisonError::into_inner);
+        mem::take(&mut *guard)
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`RwLock`] values.
+///
+/// This lets you lock the contained [`RwLock`] with a [`StackToken`] and get
+/// back guards that are bound to the lifetime of the token.
+pub trait RwLockLocalKeyExt<T> {
+    /// Locks the lock with shared read access.
+    fn read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> LockResult<RwLockReadGuard<'stack, T>>;
+
+    /// Locks the lock with exclusive write access.
+    fn write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> LockResult<RwLockWriteGuard<'stack, T>>;
+
+    /// Attempts to lock the lock with shared read access without blocking.
+    fn try_read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<RwLockReadGuard<'stack, T>>;
+
+    /// Attempts to lock the lock with exclusive write access without blocking.
+    fn try_write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<RwLockWriteGuard<'stack, T>>;
+
+    /// Takes the value out of the lock, leaving `Default::default()` in its
+    /// place.
+    ///
+    /// The value is taken even if the lock is poisoned.  Like
+    /// [`write`](Self::write) this must not be called while the current
+    /// thread holds the lock.
+    fn take(&'static self, token: &StackToken) -> T
+    where
+        T: Default;
+}
+
+impl<T: 'static> RwLockLocalKeyExt<T> for LocalKey<RwLock<T>> {
+    #[inline]
+    #[track_caller]
+    fn read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> LockResult<RwLockReadGuard<'stack, T>> {
+        self.borrow(token).read()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> LockResult<RwLockWriteGuard<'stack, T>> {
+        self.borrow(token).write()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_read<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<RwLockReadGuard<'stack, T>> {
+        self.borrow(token).try_read()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn try_write<'stack>(
+        &'static self,
+        token: &'stack StackToken,
+    ) -> TryLockResult<RwLockWriteGuard<'stack, T>> {
+        self.borrow(token).try_write()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn take(&'static self, token: &StackToken) -> T
+    where
+        T: Default,
+    {
+        let mut guard = self.write(token).unwrap_or_else(PoisonError::into_inner);
+        mem::take(&mut *guard)
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`OnceCell`] values.
+///
+/// This lets you get at the lazily initialized value with a [`StackToken`]
+/// instead of a closure.
+pub trait OnceCellLocalKeyExt<T> {
+    /// Gets the reference to the underlying value if it was initialized.
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> Option<&'stack T>;
+
+    /// Gets the contents of the cell, initializing it with `f` if the cell
+    /// was empty.
+    fn get_or_init<'stack, F: FnOnce() -> T>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack T;
+}
+
+impl<T: 'static> OnceCellLocalKeyExt<T> for LocalKey<OnceCell<T>> {
+    #[inline]
+    #[track_caller]
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> Option<&'stack T> {
+        self.borrow(token).get()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_init<'stack, F: FnOnce() -> T>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack T {
+        self.borrow(token).get_or_init(f)
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`OnceLock`] values.
+///
+/// This is the equivalent of [`OnceCellLocalKeyExt`] for [`OnceLock`].
+pub trait OnceLockLocalKeyExt<T> {
+    /// Gets the reference to the underlying value if it was initialized.
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> Option<&'stack T>;
+
+    /// Gets the contents of the lock, initializing it with `f` if the lock
+    /// was empty.
+    fn get_or_init<'stack, F: FnOnce() -> T>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack T;
+
+    /// Gets the contents of the lock, initializing it with `f` if the lock
+    /// was empty.
+    ///
+    /// If `f` fails the error is returned and the lock stays uninitialized,
+    /// so a later call can try again.
+    fn get_or_try_init<'stack, E, F: FnOnce() -> Result<T, E>>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> Result<&'stack T, E>;
+
+    /// Returns `true` if the lock was initialized.
+    ///
+    /// This never runs an initialization function.
+    fn is_initialized(&'static self, token: &StackToken) -> bool;
+}
+
+impl<T: 'static> OnceLockLocalKeyExt<T> for LocalKey<OnceLock<T>> {
+    #[inline]
+    #[track_caller]
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> Option<&'stack T> {
+        self.borrow(token).get()
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_init<'stack, F: FnOnce() -> T>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> &'stack T {
+        self.borrow(token).get_or_init(f)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn get_or_try_init<'stack, E, F: FnOnce() -> Result<T, E>>(
+        &'static self,
+        token: &'stack StackToken,
+        f: F,
+    ) -> Result<&'stack T, E> {
+        let lock = self.borrow(token);
+        if let Some(value) = lock.get() {
+            return Ok(value);
+        }
+        let value = f()?;
+        Ok(lock.get_or_init(|| value))
+    }
+
+    #[inline]
+    #[track_caller]
+    fn is_initialized(&'static self, token: &StackToken) -> bool {
+        self.borrow(token).get().is_some()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding lazily initialized
+/// values.
+///
+/// This is implemented for thread locals holding a [`LazyCell`] or a
+/// [`LazyLock`].
+pub trait LazyLocalKeyExt<T> {
+    /// Forces the evaluation of the lazy value and returns a reference to
+    /// the result.
+    fn force<'stack>(&'static self, token: &'stack StackToken) -> &'stack T;
+}
+
+impl<T: 'static, F: FnOnce() -> T + 'static> LazyLocalKeyExt<T> for LocalKey<LazyCell<T, F>> {
+    #[inline]
+    #[track_caller]
+    fn force<'stack>(&'static self, token: &'stack StackToken) -> &'stack T {
+        LazyCell::force(self.borrow(token))
+    }
+}
+
+impl<T: 'static, F: FnOnce() -> T + 'static> LazyLocalKeyExt<T> for LocalKey<LazyLock<T, F>> {
+    #[inline]
+    #[track_caller]
+    fn force<'stack>(&'static self, token: &'stack StackToken) -> &'stack T {
+        LazyLock::force(self.borrow(token))
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`Rc`] values.
+pub trait RcLocalKeyExt<T> {
+    /// Borrows the contained [`Rc`].
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> &'stack Rc<T>;
+
+    /// Returns a clone of the contained [`Rc`].
+    ///
+    /// Unlike [`get`](Self::get) the returned handle is not bound to the
+    /// lifetime of the token.
+    fn cloned(&'static self, token: &StackToken) -> Rc<T>;
+}
+
+impl<T: 'static> RcLocalKeyExt<T> for LocalKey<Rc<T>> {
+    #[inline]
+    #[track_caller]
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> &'stack Rc<T> {
+        self.borrow(token)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn cloned(&'static self, token: &StackToken) -> Rc<T> {
+        self.borrow(token).clone()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding [`Arc`] values.
+pub trait ArcLocalKeyExt<T> {
+    /// Borrows the contained [`Arc`].
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> &'stack Arc<T>;
+
+    /// Returns a clone of the contained [`Arc`].
+    ///
+    /// Unlike [`get`](Self::get) the returned handle is not bound to the
+    /// lifetime of the token and can be sent to other threads if `T` allows
+    /// it.
+    fn cloned(&'static self, token: &StackToken) -> Arc<T>;
+}
+
+impl<T: 'static> ArcLocalKeyExt<T> for LocalKey<Arc<T>> {
+    #[inline]
+    #[track_caller]
+    fn get<'stack>(&'static self, token: &'stack StackToken) -> &'stack Arc<T> {
+        self.borrow(token)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn cloned(&'static self, token: &StackToken) -> Arc<T> {
+        self.borrow(token).clone()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding a [`RefCell`] with a [`Weak`].
+pub trait WeakRefCellLocalKeyExt<T> {
+    /// Attempts to upgrade the contained [`Weak`] to an [`Rc`].
+    ///
+    /// Returns `None` if the value has been dropped.  The returned handle is
+    /// not bound to the lifetime of the token.
+    fn upgrade(&'static self, token: &StackToken) -> Option<Rc<T>>;
+}
+
+impl<T: 'static> WeakRefCellLocalKeyExt<T> for LocalKey<RefCell<Weak<T>>> {
+    #[inline]
+    #[track_caller]
+    fn upgrade(&'static self, token: &StackToken) -> Option<Rc<T>> {
+        self.as_ref(token).upgrade()
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding atomic integers.
+///
+/// This is implemented for all the integer atomics in [`std::sync::atomic`]
+/// and forwards to the methods of the same name on the atomic.
+pub trait AtomicLocalKeyExt<T> {
+    /// Loads a value from the atomic integer.
+    fn load(&'static self, token: &StackToken, order: Ordering) -> T;
+
+    /// Stores a value into the atomic integer.
+    fn store(&'static self, token: &StackToken, value: T, order: Ordering);
+
+    /// Stores a value into the atomic integer, returning the previous value.
+    fn swap(&'static self, token: &StackToken, value: T, order: Ordering) -> T;
+
+    /// Adds to the current value, returning the previous value.
+    fn fetch_add(&'static self, token: &StackToken, value: T, order: Ordering) -> T;
+
+    /// Stores `new` into the atomic integer if the current value is
+    /// `current`.
+    ///
+    /// Returns the previous value wrapped in `Ok` on success and in `Err`
+    /// otherwise.
+    fn compare_exchange(
+        &'static self,
+        token: &StackToken,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T>;
+
+    /// Fetches the value and applies `f` to it, storing the new value if
+    /// `f` returns `Some`.
+    ///
+    /// Returns the previous value wrapped in `Ok` if `f` returned `Some` and
+    /// in `Err` otherwise.
+    fn fetch_update<F: FnMut(T) -> Option<T>>(
+        &'static self,
+        token: &StackToken,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        f: F,
+    ) -> Result<T, T>;
+}
+
+macro_rules! impl_atomic_local_key_ext {
+    ($($atomic:ident => $ty:ty,)*) => {
+        $(
+            impl AtomicLocalKeyExt<$ty> for LocalKey<$atomic> {
+                #[inline]
+                #[track_caller]
+                fn load(&'static self, token: &StackToken, order: Ordering) -> $ty {
+                    self.borrow(token).load(order)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn store(&'static self, token: &StackToken, value: $ty, order: Ordering) {
+                    self.borrow(token).store(value, order)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn swap(&'static self, token: &StackToken, value: $ty, order: Ordering) -> $ty {
+                    self.borrow(token).swap(value, order)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn fetch_add(
+                    &'static self,
+                    token: &StackToken,
+                    value: $ty,
+                    order: Ordering,
+                ) -> $ty {
+                    self.borrow(token).fetch_add(value, order)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn compare_exchange(
+                    &'static self,
+                    token: &StackToken,
+                    current: $ty,
+                    new: $ty,
+                    success: Ordering,
+                    failure: Ordering,
+                ) -> Result<$ty, $ty> {
+                    self.borrow(token).compare_exchange(current, new, success, failure)
+                }
+
+                #[inline]
+                #[track_caller]
+                fn fetch_update<F: FnMut($ty) -> Option<$ty>>(
+                    &'static self,
+                    token: &StackToken,
+                    set_order: Ordering,
+                    fetch_order: Ordering,
+                    f: F,
+                ) -> Result<$ty, $ty> {
+                    self.borrow(token).fetch_update(set_order, fetch_order, f)
+                }
+            }
+        )*
+    };
+}
+
+impl_atomic_local_key_ext! {
+    AtomicI8 => i8,
+    AtomicI16 => i16,
+    AtomicI32 => i32,
+    AtomicI64 => i64,
+    AtomicIsize => isize,
+    AtomicU8 => u8,
+    AtomicU16 => u16,
+    AtomicU32 => u32,
+    AtomicU64 => u64,
+    AtomicUsize => usize,
+}
+
+/// Additional utility methods to [`LocalKey`]s holding an [`AtomicBool`].
+pub trait AtomicBoolLocalKeyExt {
+    /// Loads the value of the flag.
+    fn get(&'static self, token: &StackToken, order: Ordering) -> bool;
+
+    /// Stores a new value into the flag.
+    fn set(&'static self, token: &StackToken, value: bool, order: Ordering);
+
+    /// Flips the value of the flag, returning the previous value.
+    fn toggle(&'static self, token: &StackToken, order: Ordering) -> bool;
+}
+
+impl AtomicBoolLocalKeyExt for LocalKey<AtomicBool> {
+    #[inline]
+    #[track_caller]
+    fn get(&'static self, token: &StackToken, order: Ordering) -> bool {
+        self.borrow(token).load(order)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn set(&'static self, token: &StackToken, value: bool, order: Ordering) {
+        self.borrow(token).store(value, order)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn toggle(&'static self, token: &StackToken, order: Ordering) -> bool {
+        self.borrow(token).fetch_xor(true, order)
+    }
+}
+
+/// Additional utility methods to [`LocalKey`]s holding an [`AtomicPtr`].
+pub trait AtomicPtrLocalKeyExt<T> {
+    /// Loads the value of the pointer.
+    fn load(&'static self, token: &StackToken, order: Ordering) -> *mut T;
+
+    /// Stores a new value into the pointer.
+    fn store(&'static self, token: &StackToken, ptr: *mut T, order: Ordering);
+}
+
+impl<T> AtomicPtrLocalKeyExt<T> for LocalKey<AtomicPtr<T>> {
+    #[inline]
+    #[track_caller]
+    fn load(&'static self, token: &StackToken, order: Ordering) -> *mut T {
+        self.borrow(token).load(order)
+    }
+
+    #[inline]
+    #[track_caller]
+    fn store(&'static self, token: &StackToken, ptr: *mut T, order: Ordering) {
+        self.borrow(token).store(ptr, order)
+    }
+}
+
+#[test]
+fn test_tls_basic() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<u32> = RefCell::default(); }
+
+    stack_token!(scope);
+    *FOO.borrow(scope).borrow_mut() += 1;
+    assert_eq!(*FOO.borrow(scope).borrow(), 1);
+}
+
+#[test]
+fn test_tls_borrow_multiple() {
+    use crate::stack_token;
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    thread_local! {
+        static COUNTER: AtomicUsize = const { AtomicUsize::new(1) };
+        static VALUE: Cell<i32> = const { Cell::new(2) };
+    }
+
+    stack_token!(scope);
+    let (counter, value) = borrow2(&COUNTER, &VALUE, scope);
+    counter.fetch_add(1, Ordering::Relaxed);
+    value.set(value.get() * 2);
+    assert_eq!(COUNTER.borrow(scope).load(Ordering::Relaxed), 2);
+    assert_eq!(VALUE.borrow(scope).get(), 4);
+
+    let (a, b, c) = borrow3(&COUNTER, &VALUE, &COUNTER, scope);
+    assert!(std::ptr::eq(a, c));
+    assert_eq!(b.get(), 4);
+}
+
+#[test]
+fn test_tls_try_borrow() {
+    use crate::stack_token;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    static FAILED_IN_DROP: AtomicBool = AtomicBool::new(false);
+
+    struct Guard(u32);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            stack_token!(scope);
+            FAILED_IN_DROP.store(FOO.try_borrow(scope).is_err(), Ordering::SeqCst);
+        }
+    }
+
+    thread_local! { static FOO: Guard = const { Guard(42) }; }
+
+    thread::spawn(|| {
+        stack_token!(scope);
+        assert_eq!(FOO.try_borrow(scope).unwrap().0, 42);
+    })
+    .join()
+    .unwrap();
+    assert!(FAILED_IN_DROP.load(Ordering::SeqCst));
+}
+
+#[test]
+fn test_tls_guard() {
+    use crate::stack_token;
+
+    thread_local! { static FOO: Vec<i32> = vec![1, 2, 3]; }
+
+    stack_token!(scope);
+    let values = FOO.guard(scope);
+    assert_eq!(values.len(), 3);
+    assert_eq!(values.iter().sum::<i32>(), 6);
+    assert_eq!(format!("{:?}", values), "[1, 2, 3]");
+    assert_eq!(values.get(), &[1, 2, 3]);
+}
+
+#[test]
+fn test_tls_borrow_map() {
+    use crate::stack_token;
+
+    struct Config {
+        name: String,
+        retries: u32,
+    }
+
+    thread_local! {
+        static CONFIG: Config = Config {
+            name: "default".into(),
+            retries: 3,
+        };
+    }
+
+    stack_token!(scope);
+    let name: &str = CONFIG.borrow_map(scope, |x| x.name.as_str());
+    let retries = CONFIG.borrow_map(scope, |x| &x.retries);
+    assert_eq!(name, "default");
+    assert_eq!(*retries, 3);
+}
+
+#[test]
+fn test_tls_get_copy() {
+    use crate::stack_token;
+
+    thread_local! { static SEED: u64 = const { 0x2545_f491_4f6c_dd1d }; }
+
+    let seed = {
+        stack_token!(scope);
+        SEED.get_copy(scope)
+    };
+    assert_eq!(seed, 0x2545_f491_4f6c_dd1d);
+}
+
+#[test]
+fn test_tls_display_debug() {
+    use crate::stack_token;
+
+    thread_local! {
+        static FOO: i32 = const { 42 };
+        static BAR: &'static str = const { "Hello" };
+    }
+
+    stack_token!(scope);
+    assert_eq!(format!("{}", FOO.display(scope)), "42");
+    assert_eq!(format!("{:?}", FOO.debug(scope)), "42");
+    assert_eq!(format!("{:>5}", FOO.display(scope)), "   42");
+    assert_eq!(format!("{}", BAR.display(scope)), "Hello");
+    assert_eq!(format!("{:?}", BAR.debug(scope)), "\"Hello\"");
+}
+
+#[test]
+fn test_tls_ref_cell_display_debug() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<String> = const { RefCell::new(String::new()) }; }
+
+    stack_token!(scope);
+    let display = FOO.display_ref(scope);
+    let debug = FOO.debug_ref(scope);
+    FOO.as_mut(scope).push_str("Hello");
+    assert_eq!(format!("{}", display), "Hello");
+    assert_eq!(format!("{:>6}", display), " Hello");
+    assert_eq!(format!("{:?}", debug), "\"Hello\"");
+
+    let _guard = FOO.as_mut(scope);
+    assert_eq!(format!("{}", display), "<borrowed>");
+    assert_eq!(format!("{:?}", debug), "<borrowed>");
+}
+
+#[test]
+fn test_tls_ref_cell() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<u32> = RefCell::default(); }
+
+    stack_token!(scope);
+    *FOO.as_mut(scope) += 1;
+    assert_eq!(*FOO.as_ref(scope), 1);
+}
+
+#[test]
+fn test_tls_ref_cell_get_or_default_mut() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<u32>> = RefCell::default(); }
+
+    stack_token!(scope);
+    FOO.get_or_default_mut(scope).push(1);
+    FOO.get_or_default_mut(scope).push(2);
+    assert_eq!(*FOO.as_ref(scope), vec![1, 2]);
+}
+
+#[test]
+fn test_tls_ref_cell_nested_reads() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<u32>> = RefCell::new(vec![1, 2, 3]); }
+
+    fn sum() -> u32 {
+        stack_token!(scope);
+        let values = FOO.as_ref(scope);
+        values.iter().sum()
+    }
+
+    stack_token!(scope);
+    let outer = FOO.as_ref(scope);
+    let inner = FOO.as_ref(scope);
+    assert_eq!(sum(), 6);
+    assert_eq!(outer.len(), inner.len());
+    assert!(FOO.try_as_mut(scope).is_err());
+    drop((outer, inner));
+    assert!(FOO.try_as_mut(scope).is_ok());
+}
+
+#[test]
+fn test_tls_ref_cell_leak_ref() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<String> = RefCell::new("Hello".into()); }
+
+    stack_token!(scope);
+    let value: &str = FOO.leak_ref(scope);
+    assert_eq!(value, "Hello");
+    assert_eq!(*FOO.as_ref(scope), "Hello");
+    assert!(FOO.try_as_mut(scope).is_err());
+}
+
+#[test]
+fn test_tls_ref_cell_try() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<u32> = RefCell::default(); }
+
+    stack_token!(scope);
+    *FOO.try_as_mut(scope).unwrap() += 1;
+    let value = FOO.as_ref(scope);
+    assert_eq!(*value, 1);
+    assert_eq!(*FOO.try_as_ref(scope).unwrap(), 1);
+    assert!(FOO.try_as_mut(scope).is_err());
+    drop(value);
+    assert!(FOO.try_as_mut(scope).is_ok());
+}
+
+#[test]
+fn test_tls_ref_cell_map() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct State {
+        name: String,
+        values: Vec<u32>,
+    }
+
+    thread_local! { static FOO: RefCell<State> = RefCell::default(); }
+
+    stack_token!(scope);
+    FOO.ref_mut_map(scope, |x| &mut x.values).push(42);
+    FOO.ref_mut_map(scope, |x| &mut x.name).push_str("Hello");
+    assert_eq!(*FOO.ref_map(scope, |x| &x.values), vec![42]);
+    assert_eq!(&*FOO.ref_map(scope, |x| x.name.as_str()), "Hello");
+}
+
+#[test]
+fn test_tls_ref_cell_is_borrowed() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<u32> = RefCell::default(); }
+
+    stack_token!(scope);
+    assert!(!FOO.is_borrowed(scope));
+    assert!(!FOO.is_borrowed_mut(scope));
+    let value = FOO.as_ref(scope);
+    assert!(FOO.is_borrowed(scope));
+    assert!(!FOO.is_borrowed_mut(scope));
+    drop(value);
+    let value = FOO.as_mut(scope);
+    assert!(FOO.is_borrowed(scope));
+    assert!(FOO.is_borrowed_mut(scope));
+    drop(value);
+    assert!(!FOO.is_borrowed(scope));
+}
+
+#[test]
+fn test_tls_ref_cell_split() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct State {
+        a: Vec<u32>,
+        b: Vec<u32>,
+    }
+
+    thread_local! { static FOO: RefCell<State> = RefCell::default(); }
+
+    stack_token!(scope);
+    let (mut a, mut b) = FOO.as_mut_split(scope, |x| (&mut x.a, &mut x.b));
+    a.push(1);
+    b.push(2);
+    drop((a, b));
+    assert_eq!(FOO.as_ref(scope).a, vec![1]);
+    assert_eq!(FOO.as_ref(scope).b, vec![2]);
+}
+
+#[test]
+fn test_tls_ref_cell_replace() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<String> = RefCell::default(); }
+
+    stack_token!(scope);
+    assert_eq!(FOO.replace_value(scope, "Hello".into()), "");
+    assert_eq!(FOO.replace_with(scope, |x| format!("{} World", x)), "Hello");
+    assert_eq!(FOO.take_value(scope), "Hello World");
+    assert_eq!(*FOO.as_ref(scope), "");
+}
+
+#[test]
+fn test_tls_ref_cell_replace_and_borrow() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    thread_local! { static FOO: RefCell<Rc<i32>> = RefCell::new(Rc::new(1)); }
+
+    stack_token!(scope);
+    let old = FOO.cloned(scope);
+    assert_eq!(Rc::strong_count(&old), 2);
+
+    let mut value = FOO.replace_and_borrow(scope, Rc::new(2));
+    assert_eq!(Rc::strong_count(&old), 1);
+    assert_eq!(**value, 2);
+    *value = Rc::new(3);
+    drop(value);
+    assert_eq!(**FOO.as_ref(scope), 3);
+}
+
+#[test]
+fn test_tls_ref_cell_cloned() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]); }
+
+    let values = {
+        stack_token!(scope);
+        FOO.cloned(scope)
+    };
+    assert_eq!(values, vec![1, 2, 3]);
+}
+
+#[test]
+fn test_tls_ref_cell_snapshot() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static STACK: RefCell<Vec<i32>> = RefCell::new(vec![1, 2]); }
+
+    stack_token!(scope);
+    let snapshot = STACK.snapshot(scope);
+    STACK.as_mut(scope).push(3);
+    STACK.as_mut(scope).remove(0);
+    assert_eq!(*STACK.as_ref(scope), vec![2, 3]);
+    STACK.restore(scope, snapshot);
+    assert_eq!(*STACK.as_ref(scope), vec![1, 2]);
+}
+
+#[test]
+fn test_tls_ref_cell_as_ref_if() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static VALUE: RefCell<i32> = const { RefCell::new(42) }; }
+
+    stack_token!(scope);
+    assert_eq!(VALUE.as_ref_if(scope, |x| *x > 0).as_deref(), Some(&42));
+
+    // the borrow is not held if the predicate fails
+    let rv = VALUE.as_ref_if(scope, |x| *x < 0);
+    assert!(rv.is_none());
+    *VALUE.as_mut(scope) = -1;
+    drop(rv);
+    assert_eq!(VALUE.as_ref_if(scope, |x| *x < 0).as_deref(), Some(&-1));
+}
+
+#[test]
+fn test_tls_ref_cell_update() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static COUNTER: RefCell<u32> = RefCell::default(); }
+
+    stack_token!(scope);
+    let next = || {
+        COUNTER.update(scope, |x| {
+            *x += 1;
+            *x
+        })
+    };
+    assert_eq!(next(), 1);
+    assert_eq!(next(), 2);
+    assert!(!COUNTER.is_borrowed(scope));
+}
+
+#[test]
+fn test_tls_ref_cell_override() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::panic;
+
+    thread_local! { static FOO: RefCell<u32> = const { RefCell::new(1) }; }
+
+    stack_token!(scope);
+    let rv = FOO.with_override(scope, 2, |scope| *FOO.as_ref(scope) * 10);
+    assert_eq!(rv, 20);
+    assert_eq!(*FOO.as_ref(scope), 1);
+
+    let rv = panic::catch_unwind(|| {
+        stack_token!(scope);
+        FOO.with_override(scope, 3, |_| panic!("failed"));
+    });
+    assert!(rv.is_err());
+    assert_eq!(*FOO.as_ref(scope), 1);
+}
+
+#[test]
+#[should_panic(expected = "already borrowed")]
+fn test_tls_ref_cell_override_outer_borrow() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<u32> = const { RefCell::new(1) }; }
+
+    stack_token!(scope);
+    let _value = FOO.with_override(scope, 2, |_| FOO.as_ref(scope));
+}
+
+#[test]
+fn test_tls_ref_cell_set_scoped() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    thread_local! { static FOO: RefCell<&'static str> = const { RefCell::new("default") }; }
+
+    stack_token!(scope);
+    {
+        let _guard = FOO.set_scoped(scope, "scoped");
+        assert_eq!(*FOO.as_ref(scope), "scoped");
+    }
+    assert_eq!(*FOO.as_ref(scope), "default");
+
+    let rv = catch_unwind(AssertUnwindSafe(|| {
+        let _guard = FOO.set_scoped(scope, "scoped");
+        panic!("oops");
+    }));
+    assert!(rv.is_err());
+    assert_eq!(*FOO.as_ref(scope), "default");
+}
+
+#[test]
+fn test_tls_ref_cell_set_scoped_borrowed() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    thread_local! { static FOO: RefCell<&'static str> = const { RefCell::new("default") }; }
+
+    stack_token!(scope);
+    let guard = FOO.set_scoped(scope, "scoped");
+    let value = FOO.as_ref(scope);
+    let rv = catch_unwind(AssertUnwindSafe(|| drop(guard)));
+    assert!(rv.is_err());
+    assert_eq!(*value, "scoped");
+    drop(value);
+
+    let rv = catch_unwind(AssertUnwindSafe(|| {
+        let guard = FOO.set_scoped(scope, "unwinding");
+        // the guard is dropped first while the value is still borrowed
+        let _held = (guard, FOO.as_ref(scope));
+        panic!("oops");
+    }));
+    assert!(rv.is_err());
+    assert_eq!(*FOO.as_ref(scope), "unwinding");
+}
+
+#[test]
+fn test_tls_ref_cell_option() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Option<String>> = RefCell::default(); }
+
+    stack_token!(scope);
+    assert!(FOO.as_deref(scope).is_none());
+    assert!(FOO.as_deref_mut(scope).is_none());
+    *FOO.as_mut(scope) = Some("Hello".into());
+    FOO.as_deref_mut(scope).unwrap().push_str(" World");
+    assert_eq!(
+        FOO.as_deref(scope).as_deref().map(|x| x.as_str()),
+        Some("Hello World")
+    );
+}
+
+#[test]
+fn test_tls_ref_cell_option_as_ref_or() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Option<String>> = const { RefCell::new(None) }; }
+
+    stack_token!(scope);
+    let fallback = "fallback".to_string();
+    let value = FOO.as_ref_or(scope, &fallback);
+    assert!(matches!(value, RefOr::Default(_)));
+    assert_eq!(*value, "fallback");
+    drop(value);
+
+    *FOO.as_mut(scope) = Some("value".into());
+    let value = FOO.as_ref_or(scope, &fallback);
+    assert!(matches!(value, RefOr::Ref(_)));
+    assert_eq!(*value, "value");
+}
+
+#[test]
+fn test_tls_ref_cell_option_default() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Option<Vec<u32>>> = RefCell::default(); }
+
+    stack_token!(scope);
+    FOO.get_or_default(scope).push(1);
+    FOO.get_or_default(scope).push(2);
+    assert_eq!(*FOO.as_ref(scope), Some(vec![1, 2]));
+}
+
+#[test]
+fn test_tls_ref_cell_option_box() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    trait Counter {
+        fn bump(&mut self) -> u32;
+    }
+
+    struct Simple(u32);
+
+    impl Counter for Simple {
+        fn bump(&mut self) -> u32 {
+            self.0 += 1;
+            self.0
+        }
+    }
+
+    thread_local! { static SLOT: RefCell<Option<Box<dyn Counter>>> = RefCell::new(None); }
+
+    stack_token!(scope);
+    assert!(SLOT.peek(scope).is_none());
+    assert!(SLOT.peek_mut(scope).is_none());
+    *SLOT.as_mut(scope) = Some(Box::new(Simple(0)));
+    assert_eq!(SLOT.peek_mut(scope).unwrap().bump(), 1);
+    assert_eq!(SLOT.peek_mut(scope).unwrap().bump(), 2);
+    assert!(SLOT.peek(scope).is_some());
+}
+
+#[test]
+fn test_tls_ref_cell_any() {
+    use crate::stack_token;
+    use std::any::Any;
+    use std::cell::RefCell;
+
+    struct Plugin {
+        name: &'static str,
+    }
+
+    thread_local! { static SLOT: RefCell<Box<dyn Any>> = RefCell::new(Box::new(())); }
+
+    stack_token!(scope);
+    assert!(SLOT.downcast_ref::<Plugin>(scope).is_none());
+    *SLOT.as_mut(scope) = Box::new(Plugin { name: "demo" });
+    assert_eq!(SLOT.downcast_ref::<Plugin>(scope).unwrap().name, "demo");
+    assert!(SLOT.downcast_ref::<()>(scope).is_none());
+}
+
+#[test]
+fn test_tls_vec() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]); }
+
+    stack_token!(scope);
+    *FOO.get_mut(scope, 1).unwrap() *= 10;
+    assert_eq!(*FOO.get_ref(scope, 1).unwrap(), 20);
+    assert!(FOO.get_ref(scope, 3).is_none());
+    assert!(FOO.get_mut(scope, 3).is_none());
+}
+
+#[test]
+fn test_tls_vec_slice() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3, 4]); }
+
+    stack_token!(scope);
+    assert_eq!(&*FOO.slice(scope, 1..3), &[2, 3]);
+    assert_eq!(&*FOO.slice(scope, 2..), &[3, 4]);
+    assert!(FOO.slice(scope, 4..).is_empty());
+    assert_eq!(FOO.try_slice(scope, ..=1).as_deref(), Some(&[1, 2][..]));
+    assert!(FOO.try_slice(scope, 3..5).is_none());
+}
+
+#[test]
+#[should_panic]
+fn test_tls_vec_slice_out_of_bounds() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]); }
+
+    stack_token!(scope);
+    FOO.slice(scope, 2..5);
+}
+
+#[test]
+fn test_tls_vec_split_at_mut() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3, 4]); }
+
+    stack_token!(scope);
+    {
+        let (mut left, mut right) = FOO.split_at_mut(scope, 1);
+        left[0] = 10;
+        right.reverse();
+    }
+    assert_eq!(*FOO.as_ref(scope), vec![10, 4, 3, 2]);
+
+    let (left, right) = FOO.split_at_mut(scope, 4);
+    assert_eq!(left.len(), 4);
+    assert!(right.is_empty());
+}
+
+#[test]
+#[should_panic(expected = "mid > len")]
+fn test_tls_vec_split_at_mut_out_of_bounds() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]); }
+
+    stack_token!(scope);
+    let _ = FOO.split_at_mut(scope, 4);
+}
+
+#[test]
+fn test_tls_vec_push_pop() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::default(); }
+
+    stack_token!(scope);
+    FOO.push(scope, 1);
+    FOO.push(scope, 2);
+    assert_eq!(FOO.len(scope), 2);
+    assert_eq!(FOO.pop(scope), Some(2));
+    assert_eq!(FOO.pop(scope), Some(1));
+    assert_eq!(FOO.pop(scope), None);
+    assert_eq!(FOO.len(scope), 0);
+}
+
+#[test]
+fn test_tls_vec_swap_remove_retain() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3, 4, 5, 6]); }
+
+    stack_token!(scope);
+    assert_eq!(FOO.swap_remove(scope, 2), 3);
+    assert_eq!(*FOO.as_ref(scope), vec![1, 2, 6, 4, 5]);
+    FOO.retain(scope, |x| x % 2 == 0);
+    assert_eq!(*FOO.as_ref(scope), vec![2, 6, 4]);
+}
+
+#[test]
+fn test_tls_vec_extend_append() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::default(); }
+
+    stack_token!(scope);
+    FOO.extend(scope, 1..4);
+    let mut other = vec![4, 5];
+    FOO.append(scope, &mut other);
+    assert!(other.is_empty());
+    assert_eq!(*FOO.as_ref(scope), vec![1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_tls_vec_drain_all() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]); }
+
+    stack_token!(scope);
+    for value in FOO.drain_all(scope) {
+        if value < 3 {
+            FOO.push(scope, value * 10);
+        }
+    }
+    assert_eq!(*FOO.as_ref(scope), vec![10, 20]);
+    FOO.drain_all(scope);
+    assert_eq!(FOO.len(scope), 0);
+}
+
+#[test]
+fn test_tls_array() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<[u8; 4]> = const { RefCell::new([0; 4]) }; }
+
+    stack_token!(scope);
+    *FOO.get_mut(scope, 2) = 42;
+    assert_eq!(*FOO.get(scope, 2), 42);
+    assert_eq!(*FOO.as_ref(scope), [0, 0, 42, 0]);
+}
+
+#[test]
+#[should_panic]
+fn test_tls_array_out_of_bounds() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<[u8; 4]> = const { RefCell::new([0; 4]) }; }
+
+    stack_token!(scope);
+    FOO.get(scope, 4);
+}
+
+#[test]
+fn test_tls_hash_map() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    thread_local! { static FOO: RefCell<HashMap<String, i32>> = RefCell::default(); }
+
+    stack_token!(scope);
+    assert_eq!(FOO.insert(scope, "a".into(), 1), None);
+    assert_eq!(FOO.insert(scope, "a".into(), 2), Some(1));
+    assert_eq!(FOO.get(scope, "a").as_deref(), Some(&2));
+    assert!(FOO.get(scope, "b").is_none());
+    assert_eq!(FOO.remove(scope, "a"), Some(2));
+    assert!(FOO.get(scope, "a").is_none());
+}
+
+#[test]
+fn test_tls_hash_map_lookup() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    thread_local! {
+        static CACHE: RefCell<HashMap<&'static str, String>> =
+            RefCell::new(HashMap::from([("a", "alpha".to_string())]));
+    }
+
+    let value = {
+        stack_token!(scope);
+        assert!(CACHE.contains_key(scope, "a"));
+        assert!(!CACHE.contains_key(scope, "b"));
+        assert_eq!(CACHE.get_cloned(scope, "b"), None);
+        CACHE.get_cloned(scope, "a")
+    };
+    assert_eq!(value.as_deref(), Some("alpha"));
+}
+
+#[test]
+fn test_tls_hash_map_get_or_insert_with() {
+    use crate::stack_token;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+
+    thread_local! { static MEMO: RefCell<HashMap<u64, u64>> = RefCell::default(); }
+
+    let calls = Cell::new(0);
+    let square = |x: u64| {
+        calls.set(calls.get() + 1);
+        x * x
+    };
+
+    stack_token!(scope);
+    assert_eq!(*MEMO.get_or_insert_with(scope, 3, || square(3)), 9);
+    assert_eq!(*MEMO.get_or_insert_with(scope, 3, || square(3)), 9);
+    assert_eq!(calls.get(), 1);
+    {
+        let four = MEMO.get_or_insert_with(scope, 4, || square(4));
+        assert_eq!(*four, 16);
+        assert_eq!(MEMO.get(scope, &3).as_deref(), Some(&9));
+        assert_eq!(MEMO.as_ref(scope).len(), 2);
+    }
+    assert_eq!(calls.get(), 2);
+    MEMO.insert(scope, 4, 0);
+    assert_eq!(*MEMO.get_or_insert_with(scope, 4, || square(4)), 0);
+}
+
+#[test]
+fn test_tls_hash_set() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+
+    thread_local! { static SEEN: RefCell<HashSet<u64>> = RefCell::default(); }
+
+    stack_token!(scope);
+    assert!(SEEN.insert(scope, 1));
+    assert!(!SEEN.insert(scope, 1));
+    assert!(SEEN.insert(scope, 2));
+    assert!(SEEN.contains(scope, &1));
+    assert!(!SEEN.contains(scope, &3));
+    assert!(SEEN.remove(scope, &1));
+    assert!(!SEEN.remove(scope, &1));
+    assert!(!SEEN.contains(scope, &1));
+}
+
+#[test]
+fn test_tls_pin() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::marker::PhantomPinned;
+    use std::pin::Pin;
+
+    struct Countdown {
+        remaining: u32,
+        _pinned: PhantomPinned,
+    }
+
+    impl Countdown {
+        fn poll(self: Pin<&mut Self>) -> Option<u32> {
+            let this = unsafe { self.get_unchecked_mut() };
+            this.remaining = this.remaining.checked_sub(1)?;
+            Some(this.remaining)
+        }
+    }
+
+    thread_local! {
+        static FOO: RefCell<Pin<Box<Countdown>>> = RefCell::new(Box::pin(Countdown {
+            remaining: 2,
+            _pinned: PhantomPinned,
+        }));
+    }
+
+    stack_token!(scope);
+    assert_eq!(FOO.as_pin_mut(scope).as_mut().poll(), Some(1));
+    assert_eq!(FOO.as_pin_mut(scope).as_mut().poll(), Some(0));
+    assert_eq!(FOO.as_pin_mut(scope).as_mut().poll(), None);
+    assert_eq!(FOO.as_pin_ref(scope).remaining, 0);
+}
+
+#[test]
+fn test_tls_vec_iter() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]); }
+
+    stack_token!(scope);
+    let mut sum = 0;
+    FOO.for_each(scope, |x| sum += x);
+    assert_eq!(sum, 6);
+    assert_eq!(FOO.with_iter(scope, |iter| iter.max().copied()), Some(3));
+}
+
+#[test]
+fn test_tls_vec_deque() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    thread_local! { static FOO: RefCell<VecDeque<i32>> = RefCell::default(); }
+
+    stack_token!(scope);
+    FOO.push_back(scope, 1);
+    FOO.push_back(scope, 2);
+    FOO.push_back(scope, 3);
+    assert_eq!(FOO.len(scope), 3);
+    assert_eq!(FOO.pop_front(scope), Some(1));
+    assert_eq!(FOO.pop_front(scope), Some(2));
+    assert_eq!(FOO.pop_front(scope), Some(3));
+    assert_eq!(FOO.pop_front(scope), None);
+}
+
+#[test]
+fn test_tls_vec_deque_contiguous() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    thread_local! {
+        static FOO: RefCell<VecDeque<i32>> = RefCell::new(VecDeque::from(vec![1, 2, 3, 4]));
+    }
+
+    stack_token!(scope);
+    FOO.as_mut(scope).rotate_left(2);
+    FOO.push_back(scope, 5);
+    {
+        let mut slice = FOO.as_contiguous_mut(scope);
+        assert_eq!(&*slice, &[3, 4, 1, 2, 5]);
+        slice.sort();
+    }
+    assert_eq!(FOO.as_ref(scope).as_slices().0, &[1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn test_tls_btree_map() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+
+    thread_local! { static FOO: RefCell<BTreeMap<u32, &'static str>> = RefCell::default(); }
+
+    stack_token!(scope);
+    FOO.insert(scope, 3, "c");
+    FOO.insert(scope, 1, "a");
+    FOO.insert(scope, 2, "b");
+    assert_eq!(FOO.get(scope, &2).as_deref(), Some(&"b"));
+    let values: Vec<_> = FOO.with_range(scope, 2.., |iter| iter.map(|(_, v)| *v).collect());
+    assert_eq!(values, vec!["b", "c"]);
+    assert_eq!(FOO.remove(scope, &2), Some("b"));
+    let keys: Vec<_> = FOO.with_range(scope, .., |iter| iter.map(|(k, _)| *k).collect());
+    assert_eq!(keys, vec![1, 3]);
+}
+
+#[test]
+fn test_tls_string_cow() {
+    use crate::stack_token;
+    use std::borrow::Cow;
+    use std::cell::RefCell;
+
+    thread_local! { static FOO: RefCell<String> = RefCell::new("Hello".into()); }
+
+    stack_token!(scope);
+    let len = FOO.with_cow(scope, |value| {
+        assert!(matches!(value, Cow::Borrowed("Hello")));
+        value.len()
+    });
+    assert_eq!(len, 5);
+}
+
+#[test]
+fn test_tls_swap() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+
+    thread_local! {
+        static A: RefCell<u32> = const { RefCell::new(1) };
+        static B: RefCell<u32> = const { RefCell::new(2) };
+    }
+
+    stack_token!(scope);
+    swap(&A, &B, scope);
+    assert_eq!((*A.as_ref(scope), *B.as_ref(scope)), (2, 1));
+    swap(&A, &A, scope);
+    assert_eq!(*A.as_ref(scope), 2);
+}
+
+#[test]
+fn test_tls_track_caller() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::panic;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    thread_local! { static FOO: RefCell<u32> = RefCell::default(); }
+
+    let location = Arc::new(Mutex::new(None));
+    let hook = panic::take_hook();
+    panic::set_hook({
+        let location = location.clone();
+        let test_thread = thread::current().id();
+        Box::new(move |info| {
+            if thread::current().id() == test_thread {
+                *location.lock().unwrap() =
+                    info.location().map(|x| (x.file().to_string(), x.line()));
+            }
+        })
+    });
+    let expected_line = line!() + 4;
+    let rv = panic::catch_unwind(|| {
+        stack_token!(scope);
+        let _a = FOO.as_ref(scope);
+        let _b = FOO.as_mut(scope);
+    });
+    panic::set_hook(hook);
+
+    assert!(rv.is_err());
+    assert_eq!(
+        *location.lock().unwrap(),
+        Some((file!().to_string(), expected_line))
+    );
+}
+
+#[test]
+fn test_tls_cell() {
+    use crate::stack_token;
+    use std::cell::Cell;
+
+    thread_local! { static FOO: Cell<u32> = Cell::default(); }
+
+    stack_token!(scope);
+    FOO.set_value(scope, FOO.get_value(scope) + 1);
+    assert_eq!(FOO.get_value(scope), 1);
+    assert_eq!(FOO.replace_value(scope, 42), 1);
+    assert_eq!(FOO.take_value(scope), 42);
+    assert_eq!(FOO.get_value(scope), 0);
+}
+
+#[test]
+fn test_tls_cell_update() {
+    use crate::stack_token;
+    use std::cell::Cell;
+
+    thread_local! { static FOO: Cell<i32> = const { Cell::new(21) }; }
+
+    stack_token!(scope);
+    FOO.update_with(scope, |x| x * 2);
+    assert_eq!(FOO.get(), 42);
+}
+
+#[test]
+fn test_tls_cell_swap() {
+    use crate::stack_token;
+    use std::cell::Cell;
+
+    thread_local! {
+        static A: Cell<u32> = const { Cell::new(1) };
+        static B: Cell<u32> = const { Cell::new(2) };
+    }
+
+    stack_token!(scope);
+    A.swap(scope, &B);
+    assert_eq!(A.get(), 2);
+    assert_eq!(B.get(), 1);
+    A.swap(scope, &A);
+    assert_eq!(A.get(), 2);
+}
+
+#[test]
+fn test_tls_unsafe_cell() {
+    use crate::stack_token;
+    use std::cell::UnsafeCell;
+
+    thread_local! { static FOO: UnsafeCell<u32> = const { UnsafeCell::new(0) }; }
+
+    stack_token!(scope);
+    let ptr = FOO.as_ptr(scope);
+    unsafe {
+        *ptr += 42;
+        assert_eq!(*FOO.as_ptr(scope), 42);
+    }
+}
+
+#[test]
+fn test_tls_non_null() {
+    use crate::stack_token;
+    use std::cell::{RefCell, UnsafeCell};
+
+    thread_local! {
+        static FOO: u32 = const { 42 };
+        static BAR: RefCell<u32> = const { RefCell::new(1) };
+        static BAZ: UnsafeCell<u32> = const { UnsafeCell::new(2) };
+    }
+
+    stack_token!(scope);
+    let ptr = FOO.as_non_null(scope);
+    assert_eq!(unsafe { *ptr.as_ptr() }, 42);
+    assert!(std::ptr::eq(ptr.as_ptr(), FOO.borrow(scope)));
+
+    let ptr = BAR.as_non_null_mut(scope);
+    unsafe { *ptr.as_ptr() += 10 };
+    assert_eq!(*BAR.as_ref(scope), 11);
+
+    let ptr = BAZ.as_non_null_mut(scope);
+    unsafe { *ptr.as_ptr() += 10 };
+    assert_eq!(unsafe { *BAZ.as_ptr(scope) }, 12);
+}
+
+#[test]
+fn test_tls_mutex() {
+    use crate::stack_token;
+    use std::sync::Mutex;
+
+    thread_local! { static FOO: Mutex<Vec<i32>> = Mutex::default(); }
+
+    stack_token!(scope);
+    FOO.lock(scope).unwrap().push(42);
+    let guard = FOO.try_lock(scope).unwrap();
+    assert_eq!(*guard, vec![42]);
+    assert!(FOO.try_lock(scope).is_err());
+}
+
+#[test]
+fn test_tls_mutex_take() {
+    use crate::stack_token;
+    use std::sync::Mutex;
+
+    thread_local! { static FOO: Mutex<Vec<i32>> = Mutex::new(vec![1, 2]); }
+
+    stack_token!(scope);
+    assert_eq!(FOO.take(scope), vec![1, 2]);
+    assert!(FOO.lock(scope).unwrap().is_empty());
+}
+
+#[test]
+fn test_tls_rw_lock() {
+    use crate::stack_token;
+    use std::sync::RwLock;
+
+    thread_local! { static FOO: RwLock<u32> = RwLock::default(); }
+
+    stack_token!(scope);
+    *FOO.write(scope).unwrap() = 42;
+    let a = FOO.read(scope).unwrap();
+    let b = FOO.try_read(scope).unwrap();
+    assert_eq!(*a + *b, 84);
+    assert!(FOO.try_write(scope).is_err());
+    drop((a, b));
+    let guard = FOO.try_write(scope).unwrap();
+    assert!(FOO.try_read(scope).is_err());
+    drop(guard);
+    assert!(FOO.try_read(scope).is_ok());
+}
+
+#[test]
+fn test_tls_rw_lock_take() {
+    use crate::stack_token;
+    use std::sync::RwLock;
+
+    thread_local! { static FOO: RwLock<Vec<i32>> = RwLock::new(vec![1, 2]); }
+
+    stack_token!(scope);
+    assert_eq!(FOO.take(scope), vec![1, 2]);
+    assert!(FOO.read(scope).unwrap().is_empty());
+}
+
+#[test]
+fn test_tls_once_cell() {
+    use crate::stack_token;
+    use std::cell::OnceCell;
+
+    thread_local! { static FOO: OnceCell<String> = OnceCell::default(); }
+
+    stack_token!(scope);
+    assert_eq!(FOO.get(scope), None);
+    let a = FOO.get_or_init(scope, || "Hello".to_string());
+    let b = FOO.get_or_init(scope, || unreachable!());
+    assert_eq!(a, "Hello");
+    assert!(std::ptr::eq(a, b));
+    assert_eq!(FOO.get(scope).map(|x| x.as_str()), Some("Hello"));
+}
+
+#[test]
+fn test_tls_once_lock() {
+    use crate::stack_token;
+    use std::sync::OnceLock;
+
+    thread_local! { static FOO: OnceLock<String> = OnceLock::default(); }
+
+    stack_token!(scope);
+    assert_eq!(FOO.get(scope), None);
+    let a = FOO.get_or_init(scope, || "Hello".to_string());
+    let b = FOO.get_or_init(scope, || unreachable!());
+    assert_eq!(a, "Hello");
+    assert!(std::ptr::eq(a, b));
+    assert_eq!(FOO.get(scope).map(|x| x.as_str()), Some("Hello"));
+}
+
+#[test]
+fn test_tls_once_lock_is_initialized() {
+    use crate::stack_token;
+    use std::sync::OnceLock;
+
+    thread_local! { static FOO: OnceLock<u32> = OnceLock::default(); }
+
+    stack_token!(scope);
+    assert!(!FOO.is_initialized(scope));
+    assert_eq!(FOO.get(scope), None);
+    FOO.get_or_init(scope, || 42);
+    assert!(FOO.is_initialized(scope));
+}
+
+#[test]
+fn test_tls_once_lock_get_or_try_init() {
+    use crate::stack_token;
+    use std::sync::OnceLock;
+
+    thread_local! { static FOO: OnceLock<u32> = OnceLock::default(); }
+
+    stack_token!(scope);
+    assert_eq!(FOO.get_or_try_init(scope, || Err("nope")), Err("nope"));
+    assert!(!FOO.is_initialized(scope));
+    assert_eq!(FOO.get_or_try_init(scope, || Ok::<_, ()>(42)), Ok(&42));
+    assert_eq!(FOO.get_or_try_init(scope, || Err("nope")), Ok(&42));
+}
+
+#[test]
+fn test_tls_lazy() {
+    use crate::stack_token;
+    use std::cell::Cell;
+    use std::sync::LazyLock;
+
+    thread_local! {
+        static CALLS: Cell<u32> = const { Cell::new(0) };
+        static FOO: LazyLock<String> = LazyLock::new(|| {
+            CALLS.with(|calls| calls.set(calls.get() + 1));
+            "Hello".into()
+        });
+    }
+
+    stack_token!(scope);
+    assert_eq!(CALLS.with(Cell::get), 0);
+    assert_eq!(FOO.force(scope), "Hello");
+    assert_eq!(FOO.force(scope).len(), 5);
+    assert_eq!(CALLS.with(Cell::get), 1);
+}
+
+#[test]
+fn test_tls_lazy_cell() {
+    use crate::stack_token;
+    use std::cell::{Cell, LazyCell};
+
+    thread_local! {
+        static CALLS: Cell<u32> = const { Cell::new(0) };
+        static FOO: LazyCell<Vec<u32>> = LazyCell::new(|| {
+            CALLS.with(|calls| calls.set(calls.get() + 1));
+            vec![1, 2, 3]
+        });
+    }
+
+    stack_token!(scope);
+    assert_eq!(CALLS.with(Cell::get), 0);
+    assert_eq!(FOO.force(scope), &[1, 2, 3]);
+    assert_eq!(FOO.force(scope).len(), 3);
+    assert_eq!(CALLS.with(Cell::get), 1);
+}
+
+#[test]
+fn test_tls_stack_borrow() {
+    use crate::stack_token;
+    use std::cell::Cell;
+
+    thread_local! { static FOO: Cell<u32> = const { Cell::new(42) }; }
+
+    stack_token!(scope);
+    assert_eq!(FOO.stack_borrow(scope).get(), 42);
+}
+
+#[test]
+fn test_tls_stack_borrow_generic() {
+    use crate::{stack_token, StackTokenCell};
+
+    thread_local! { static FOO: String = "Hello".into(); }
+
+    fn len<P: StackBorrow<Target = String> + ?Sized>(source: &P) -> usize {
+        stack_token!(scope);
+        source.stack_borrow(scope).len()
+    }
+
+    let cell = StackTokenCell::new("Hello World".to_string());
+    assert_eq!(len(&FOO), 5);
+    assert_eq!(len(&&FOO), 5);
+    assert_eq!(len(&cell), 11);
+    assert_eq!(len(&&cell), 11);
+}
+
+#[test]
+fn test_tls_stack_ref_generic() {
+    use crate::stack_token;
+
+    thread_local! { static FOO: String = "Hello".into(); }
+
+    fn len<R: StackRef<Target = String> + ?Sized>(source: &R) -> usize {
+        stack_token!(scope);
+        source.stack_ref(scope).len()
+    }
+
+    let value = "Hello World".to_string();
+    assert_eq!(len(&FOO), 5);
+    assert_eq!(len(&&value), 11);
+}
+
+#[test]
+fn test_tls_atomic() {
+    use crate::stack_token;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    thread_local! { static FOO: AtomicUsize = const { AtomicUsize::new(0) }; }
+
+    stack_token!(scope);
+    assert_eq!(FOO.fetch_add(scope, 1, Ordering::Relaxed), 0);
+    assert_eq!(FOO.load(scope, Ordering::Relaxed), 1);
+    FOO.store(scope, 23, Ordering::Relaxed);
+    assert_eq!(FOO.swap(scope, 42, Ordering::Relaxed), 23);
+    assert_eq!(FOO.load(scope, Ordering::Relaxed), 42);
+}
+
+#[test]
+fn test_tls_atomic_compare_exchange() {
+    use crate::stack_token;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    thread_local! { static FOO: AtomicU32 = const { AtomicU32::new(1) }; }
+
+    stack_token!(scope);
+    let (ok, fail) = (Ordering::AcqRel, Ordering::Acquire);
+    assert_eq!(FOO.compare_exchange(scope, 1, 2, ok, fail), Ok(1));
+    assert_eq!(FOO.compare_exchange(scope, 1, 3, ok, fail), Err(2));
+    assert_eq!(FOO.load(scope, Ordering::Relaxed), 2);
+
+    let double = |x: u32| x.checked_mul(2);
+    assert_eq!(FOO.fetch_update(scope, ok, fail, double), Ok(2));
+    assert_eq!(FOO.fetch_update(scope, ok, fail, |_| None), Err(4));
+    assert_eq!(FOO.load(scope, Ordering::Relaxed), 4);
+}
+
+#[test]
+fn test_tls_rc() {
+    use crate::stack_token;
+    use std::rc::Rc;
+
+    thread_local! { static FOO: Rc<String> = Rc::new("Hello".into()); }
+
+    stack_token!(scope);
+    assert_eq!(Rc::strong_count(FOO.get(scope)), 1);
+    let handle = FOO.cloned(scope);
+    assert_eq!(Rc::strong_count(FOO.get(scope)), 2);
+    assert_eq!(*handle, "Hello");
+    drop(handle);
+    assert_eq!(Rc::strong_count(FOO.get(scope)), 1);
+}
+
+#[test]
+fn test_tls_arc() {
+    use crate::stack_token;
+    use std::sync::Arc;
+
+    thread_local! { static CONFIG: Arc<String> = Arc::new("Hello".into()); }
+
+    stack_token!(scope);
+    assert_eq!(Arc::strong_count(CONFIG.get(scope)), 1);
+    let handle = CONFIG.cloned(scope);
+    assert_eq!(Arc::strong_count(CONFIG.get(scope)), 2);
+    let len = std::thread::spawn(move || handle.len()).join().unwrap();
+    assert_eq!(len, 5);
+    assert_eq!(Arc::strong_count(CONFIG.get(scope)), 1);
+}
+
+#[test]
+fn test_tls_weak() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::rc::{Rc, Weak};
+
+    thread_local! { static PARENT: RefCell<Weak<String>> = RefCell::default(); }
+
+    stack_token!(scope);
+    assert!(PARENT.upgrade(scope).is_none());
+
+    let node = Rc::new("node".to_string());
+    *PARENT.as_mut(scope) = Rc::downgrade(&node);
+    assert_eq!(
+        PARENT.upgrade(scope).as_deref().map(|x| x.as_str()),
+        Some("node")
+    );
+
+    drop(node);
+    assert!(PARENT.upgrade(scope).is_none());
+}
+
+#[test]
+fn test_tls_atomic_bool() {
+    use crate::stack_token;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    thread_local! { static FOO: AtomicBool = const { AtomicBool::new(false) }; }
+
+    stack_token!(scope);
+    assert!(!FOO.get(scope, Ordering::Relaxed));
+    assert!(!FOO.toggle(scope, Ordering::Relaxed));
+    assert!(FOO.get(scope, Ordering::Relaxed));
+    FOO.set(scope, false, Ordering::Relaxed);
+    assert!(!FOO.get(scope, Ordering::Relaxed));
+}
+
+#[test]
+fn test_tls_atomic_ptr() {
+    use crate::stack_token;
+    use std::ptr;
+    use std::sync::atomic::{AtomicPtr, Ordering};
+
+    thread_local! { static FOO: AtomicPtr<u32> = const { AtomicPtr::new(ptr::null_mut()) }; }
+
+    let mut value = 42;
+    stack_token!(scope);
+    assert!(FOO.load(scope, Ordering::Relaxed).is_null());
+    FOO.store(scope, &mut value, Ordering::Relaxed);
+    assert_eq!(unsafe { *FOO.load(scope, Ordering::Relaxed) }, 42);
+}
+
+#[test]
+fn test_tls_clear() {
+    use crate::stack_token;
+    use std::cell::RefCell;
+    use std::collections::{HashMap, HashSet, VecDeque};
+
+    thread_local! {
+        static VEC: RefCell<Vec<i32>> = RefCell::new(vec![1, 2, 3]);
+        static DEQUE: RefCell<VecDeque<i32>> = RefCell::new((1..4).collect());
+        static MAP: RefCell<HashMap<i32, i32>> = RefCell::new(HashMap::from([(1, 2)]));
+        static SET: RefCell<HashSet<i32>> = RefCell::new(HashSet::from([1, 2]));
+    }
+
+    stack_token!(scope);
+    VecRefCellLocalKeyExt::clear(&VEC, scope);
+    VecDequeRefCellLocalKeyExt::clear(&DEQUE, scope);
+    HashMapRefCellLocalKeyExt::clear(&MAP, scope);
+    HashSetRefCellLocalKeyExt::clear(&SET, scope);
+    assert!(VEC.as_ref(scope).is_empty());
+    assert!(DEQUE.as_ref(scope).is_empty());
+    assert!(MAP.as_ref(scope).is_empty());
+    assert!(SET.as_ref(scope).is_empty());
+}
+
+#[test]
+#[cfg(all(feature = "track-tokens", debug_assertions))]
+#[should_panic(expected = "without an active stack token")]
+fn test_tls_track_tokens_untracked_borrow() {
+    thread_local! { static FOO: u32 = const { 42 }; }
+
+    let token = unsafe { StackToken::new_unchecked() };
+    assert_eq!(crate::current_token_depth(), 0);
+    FOO.borrow(&token);
+}
+
+#[test]
+#[cfg(all(feature = "track-tokens", debug_assertions))]
+fn test_tls_track_tokens_new_unchecked() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    thread_local! { static FOO: u32 = const { 42 }; }
+
+    let token = unsafe { StackToken::new_unchecked() };
+    crate::check_untracked_borrows(false);
+    assert_eq!(*FOO.borrow(&token), 42);
+    crate::check_untracked_borrows(true);
+    assert!(catch_unwind(AssertUnwindSafe(|| *FOO.borrow(&token))).is_err());
+}
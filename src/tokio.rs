@@ -0,0 +1,99 @@
+use tokio::task::LocalKey;
+
+use crate::tls::bind_to_token;
+use crate::StackToken;
+
+/// Adds [`StackToken`] support to task locals declared with
+/// `tokio::task_local!`.
+///
+/// This is intended for the synchronous sections of a task.  Unlike a thread
+/// local the value of a task local only stays in place while the task is
+/// polled: it is moved out again every time the task yields at an `.await`
+/// and whenever [`LocalKey::scope`] or [`LocalKey::sync_scope`] set a new
+/// value for the same key.  A stack token does not know about either, which
+/// is why borrowing is `unsafe`.
+///
+/// ```
+/// use stack_tokens::{stack_token, TaskLocalKeyExt};
+///
+/// tokio::task_local! {
+///     static REQUEST_ID: String;
+/// }
+///
+/// fn log(message: &str) {
+///     stack_token!(scope);
+///     // SAFETY: no await point and no new scope while the borrow is held
+///     let request_id = unsafe { REQUEST_ID.borrow(scope) };
+///     println!("[{}] {}", request_id, message);
+/// }
+///
+/// REQUEST_ID.sync_scope("42".into(), || log("hello"));
+/// ```
+pub trait TaskLocalKeyExt<T> {
+    /// Borrows the value from the task local with a [`StackToken`].
+    ///
+    /// # Panics
+    ///
+    /// This panics if no value is set for the task local.
+    ///
+    /// # Safety
+    ///
+    /// The borrow must not be held across an `.await` and no new value must
+    /// be set for the same key with [`LocalKey::scope`] or
+    /// [`LocalKey::sync_scope`] while the borrow is alive.  The token must
+    /// therefore not be created in an `async` block or function that awaits
+    /// while the token is in scope.
+    unsafe fn borrow<'stack>(&'static self, token: &'stack StackToken) -> &'stack T;
+
+    /// Borrows the value from the task local with a [`StackToken`] if one
+    /// is set.
+    ///
+    /// Returns `None` if no value is set for the task local.
+    ///
+    /// # Safety
+    ///
+    /// The same rules as for [`borrow`](Self::borrow) apply.
+    unsafe fn try_borrow<'stack>(&'static self, token: &'stack StackToken) -> Option<&'stack T>;
+}
+
+impl<T: 'static> TaskLocalKeyExt<T> for LocalKey<T> {
+    #[inline]
+    #[track_caller]
+    unsafe fn borrow<'stack>(&'static self, token: &'stack StackToken) -> &'stack T {
+        self.with(|value| bind_to_token(value, token))
+    }
+
+    #[inline]
+    #[track_caller]
+    unsafe fn try_borrow<'stack>(&'static self, token: &'stack StackToken) -> Option<&'stack T> {
+        self.try_with(|value| bind_to_token(value, token)).ok()
+    }
+}
+
+#[tokio::test]
+async fn test_tokio_task_local() {
+    use crate::stack_token;
+
+    tokio::task_local! { static FOO: Vec<u32>; }
+
+    fn sum() -> u32 {
+        stack_token!(scope);
+        unsafe { FOO.borrow(scope) }.iter().sum()
+    }
+
+    fn is_set() -> bool {
+        stack_token!(scope);
+        unsafe { FOO.try_borrow(scope) }.is_some()
+    }
+
+    let rv = FOO
+        .scope(vec![1, 2, 3], async {
+            let before = sum();
+            tokio::task::yield_now().await;
+            before + sum()
+        })
+        .await;
+    assert_eq!(rv, 12);
+    assert!(!is_set());
+    assert!(FOO.sync_scope(vec![], is_set));
+}
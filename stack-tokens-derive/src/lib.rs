@@ -0,0 +1,255 @@
+//! Derive support for the `stack-tokens` crate.
+//!
+//! This crate should not be used directly, instead enable the `derive`
+//! feature of `stack-tokens` and use the re-exported derive from there.
+//!
+//! ```
+//! use stack_tokens::{stack_token, StackBorrow, StackTokenCell};
+//!
+//! #[derive(StackBorrow)]
+//! struct Proxy(StackTokenCell<u32>);
+//!
+//! stack_token!(scope);
+//! let proxy = Proxy(StackTokenCell::new(42));
+//! assert_eq!(*proxy.stack_borrow(scope), 42);
+//! ```
+use proc_macro::{Delimiter, Spacing, TokenStream, TokenTree};
+
+/// Derives `StackBorrow` for a struct with a single field.
+///
+/// The implementation forwards to the `StackBorrow` implementation of the
+/// wrapped field.
+#[proc_macro_derive(StackBorrow)]
+pub fn derive_stack_borrow(input: TokenStream) -> TokenStream {
+    match expand(input) {
+        Ok(rv) => rv,
+        Err(msg) => format!("::core::compile_error!({:?});", msg)
+            .parse()
+            .unwrap(),
+    }
+}
+
+struct Field {
+    accessor: String,
+    ty: String,
+}
+
+fn expand(input: TokenStream) -> Result<TokenStream, String> {
+    let mut tokens = input.into_iter().peekable();
+
+    // skip attributes and visibility until we reach the item keyword
+    loop {
+        match tokens.next() {
+            Some(TokenTree::Ident(ident)) if ident.to_string() == "struct" => break,
+            Some(TokenTree::Ident(ident)) if matches!(&ident.to_string()[..], "enum" | "union") => {
+                return Err("StackBorrow can only be derived for structs".into());
+            }
+            Some(_) => {}
+            None => return Err("expected a struct".into()),
+        }
+    }
+
+    let name = match tokens.next() {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        _ => return Err("expected a struct name".into()),
+    };
+
+    let mut generics = Vec::new();
+    if matches!(tokens.peek(), Some(TokenTree::Punct(p)) if p.as_char() == '<') {
+        tokens.next();
+        let mut depth = 1;
+        let mut last_joint_dash = false;
+        for token in tokens.by_ref() {
+            if let TokenTree::Punct(ref p) = token {
+                match p.as_char() {
+                    '<' => depth += 1,
+                    '>' if !last_joint_dash => depth -= 1,
+                    _ => {}
+                }
+                last_joint_dash = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+            } else {
+                last_joint_dash = false;
+            }
+            if depth == 0 {
+                break;
+            }
+            generics.push(token);
+        }
+    }
+
+    let fields = match tokens.next() {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+            parse_fields(group.stream(), false)?
+        }
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Brace => {
+            parse_fields(group.stream(), true)?
+        }
+        Some(TokenTree::Ident(ident)) if ident.to_string() == "where" => {
+            return Err("StackBorrow cannot be derived for structs with where clauses".into());
+        }
+        _ => return Err("StackBorrow cannot be derived for unit structs".into()),
+    };
+
+    if matches!(tokens.next(), Some(TokenTree::Ident(ident)) if ident.to_string() == "where") {
+        return Err("StackBorrow cannot be derived for structs with where clauses".into());
+    }
+
+    if fields.len() != 1 {
+        return Err("StackBorrow can only be derived for structs with one field".into());
+    }
+    let field = &fields[0];
+
+    let params = split_top_level(generics);
+    let impl_generics = params
+        .iter()
+        .map(|param| tokens_to_string(strip_default(param)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let type_generics = params
+        .iter()
+        .map(|param| generic_param_name(param))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Ok(format!(
+        "impl<{impl_generics}> ::stack_tokens::StackBorrow for {name}<{type_generics}>
+        where
+            {ty}: ::stack_tokens::StackBorrow,
+        {{
+            type Target = <{ty} as ::stack_tokens::StackBorrow>::Target;
+
+            fn stack_borrow<'__stack>(
+                &'__stack self,
+                token: &'__stack ::stack_tokens::StackToken,
+            ) -> &'__stack Self::Target {{
+                ::stack_tokens::StackBorrow::stack_borrow(&self.{accessor}, token)
+            }}
+        }}",
+        impl_generics = impl_generics,
+        name = name,
+        type_generics = type_generics,
+        ty = field.ty,
+        accessor = field.accessor,
+    )
+    .parse()
+    .unwrap())
+}
+
+fn parse_fields(stream: TokenStream, named: bool) -> Result<Vec<Field>, String> {
+    let mut rv = Vec::new();
+    for (idx, tokens) in split_top_level(stream.into_iter().collect())
+        .into_iter()
+        .enumerate()
+    {
+        let mut tokens = tokens.into_iter().peekable();
+
+        // skip attributes and visibility
+        loop {
+            match tokens.peek() {
+                Some(TokenTree::Punct(p)) if p.as_char() == '#' => {
+                    tokens.next();
+                    tokens.next();
+                }
+                Some(TokenTree::Ident(ident)) if ident.to_string() == "pub" => {
+                    tokens.next();
+                    if matches!(tokens.peek(), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis)
+                    {
+                        tokens.next();
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let accessor = if named {
+            let accessor = match tokens.next() {
+                Some(TokenTree::Ident(ident)) => ident.to_string(),
+                _ => return Err("expected a field name".into()),
+            };
+            match tokens.next() {
+                Some(TokenTree::Punct(p)) if p.as_char() == ':' => {}
+                _ => return Err("expected a colon after the field name".into()),
+            }
+            accessor
+        } else {
+            idx.to_string()
+        };
+
+        rv.push(Field {
+            accessor,
+            ty: tokens_to_string(&tokens.collect::<Vec<_>>()),
+        });
+    }
+    Ok(rv)
+}
+
+/// Splits tokens by commas that are not nested in angle brackets.
+fn split_top_level(tokens: Vec<TokenTree>) -> Vec<Vec<TokenTree>> {
+    let mut rv = Vec::new();
+    let mut current = Vec::new();
+    let mut depth = 0usize;
+    let mut last_joint_dash = false;
+    for token in tokens {
+        if let TokenTree::Punct(ref p) = token {
+            match p.as_char() {
+                '<' => depth += 1,
+                // `->` in fn pointer types does not close an angle bracket
+                '>' if !last_joint_dash => depth = depth.saturating_sub(1),
+                ',' if depth == 0 => {
+                    rv.push(std::mem::take(&mut current));
+                    continue;
+                }
+                _ => {}
+            }
+            last_joint_dash = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+        } else {
+            last_joint_dash = false;
+        }
+        current.push(token);
+    }
+    if !current.is_empty() {
+        rv.push(current);
+    }
+    rv
+}
+
+/// Returns the name of a generic parameter as it is used in the type.
+fn generic_param_name(param: &[TokenTree]) -> String {
+    match param {
+        [TokenTree::Punct(p), TokenTree::Ident(ident), ..] if p.as_char() == '\'' => {
+            format!("'{}", ident)
+        }
+        [TokenTree::Ident(kw), TokenTree::Ident(ident), ..] if kw.to_string() == "const" => {
+            ident.to_string()
+        }
+        [TokenTree::Ident(ident), ..] => ident.to_string(),
+        _ => tokens_to_string(param),
+    }
+}
+
+/// Strips the default value from a generic parameter.
+///
+/// Only an `=` outside of angle brackets starts the default, others belong to
+/// associated type bindings in the bounds (`T: Deref<Target = u32>`).
+fn strip_default(param: &[TokenTree]) -> &[TokenTree] {
+    let mut depth = 0usize;
+    let mut last_joint_dash = false;
+    for (idx, token) in param.iter().enumerate() {
+        if let TokenTree::Punct(p) = token {
+            match p.as_char() {
+                '<' => depth += 1,
+                '>' if !last_joint_dash => depth = depth.saturating_sub(1),
+                '=' if depth == 0 => return &param[..idx],
+                _ => {}
+            }
+            last_joint_dash = p.as_char() == '-' && p.spacing() == Spacing::Joint;
+        } else {
+            last_joint_dash = false;
+        }
+    }
+    param
+}
+
+fn tokens_to_string(tokens: &[TokenTree]) -> String {
+    tokens.iter().cloned().collect::<TokenStream>().to_string()
+}
@@ -0,0 +1,45 @@
+use std::thread::LocalKey;
+
+use stack_tokens::{stack_token, StackBorrow, StackTokenCell};
+
+thread_local! {
+    static FOO: u32 = const { 42 };
+}
+
+#[derive(StackBorrow)]
+struct MyProxy(&'static LocalKey<u32>);
+
+#[derive(StackBorrow)]
+struct NamedProxy<'a, T: Default = u32> {
+    #[allow(dead_code)]
+    inner: &'a StackTokenCell<T>,
+}
+
+#[derive(StackBorrow)]
+struct Wrap<T: StackBorrow<Target = u32>>(T);
+
+#[derive(StackBorrow)]
+struct WrapWithDefault<T: StackBorrow<Target = u32> = &'static LocalKey<u32>>(T);
+
+#[test]
+fn test_derive_tuple_struct() {
+    stack_token!(scope);
+    let proxy = MyProxy(&FOO);
+    assert_eq!(*proxy.stack_borrow(scope), 42);
+}
+
+#[test]
+fn test_derive_named_struct() {
+    let cell = StackTokenCell::new(vec![1, 2, 3]);
+    stack_token!(scope);
+    let proxy = NamedProxy { inner: &cell };
+    assert_eq!(proxy.stack_borrow(scope).len(), 3);
+}
+
+#[test]
+fn test_derive_associated_type_binding() {
+    stack_token!(scope);
+    assert_eq!(*Wrap(&FOO).stack_borrow(scope), 42);
+    let proxy: WrapWithDefault = WrapWithDefault(&FOO);
+    assert_eq!(*proxy.stack_borrow(scope), 42);
+}
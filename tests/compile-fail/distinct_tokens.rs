@@ -0,0 +1,8 @@
+use stack_tokens::{stack_token, StackToken};
+
+fn same_scope<'id>(_a: &StackToken<'id>, _b: &StackToken<'id>) {}
+
+fn main() {
+    stack_token!(a, b);
+    same_scope(a, b);
+}
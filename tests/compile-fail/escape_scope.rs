@@ -0,0 +1,13 @@
+use stack_tokens::{stack_token, LocalKeyExt};
+
+thread_local! {
+    static FOO: u32 = 42;
+}
+
+fn main() {
+    let value = {
+        stack_token!(scope);
+        FOO.borrow(scope)
+    };
+    assert_eq!(*value, 42);
+}
@@ -0,0 +1,10 @@
+use stack_tokens::{stack_token, StackToken};
+
+fn make_token() -> &'static StackToken<'static> {
+    stack_token!(scope);
+    scope
+}
+
+fn main() {
+    make_token();
+}
@@ -0,0 +1,12 @@
+use stack_tokens::{stack_token, RefCellLocalKeyExt};
+use std::cell::RefCell;
+
+thread_local! {
+    static FOO: RefCell<u32> = RefCell::new(1);
+}
+
+fn main() {
+    stack_token!(scope);
+    let value = FOO.with_override(scope, 2, |scope| FOO.as_ref(scope));
+    assert_eq!(*value, 2);
+}
@@ -0,0 +1,14 @@
+use stack_tokens::{stack_token, LocalKeyExt};
+
+thread_local! {
+    static FOO: u32 = 42;
+}
+
+fn get() -> &'static u32 {
+    stack_token!(scope);
+    FOO.borrow(scope)
+}
+
+fn main() {
+    assert_eq!(*get(), 42);
+}
@@ -0,0 +1,16 @@
+use stack_tokens::{stack_token, RefCellLocalKeyExt};
+use std::cell::{Ref, RefCell};
+
+thread_local! {
+    static FOO: RefCell<Vec<u32>> = RefCell::default();
+}
+
+struct Holder {
+    value: Option<Ref<'static, Vec<u32>>>,
+}
+
+fn main() {
+    let mut holder = Holder { value: None };
+    stack_token!(scope);
+    holder.value = Some(FOO.as_ref(scope));
+}
@@ -0,0 +1,13 @@
+use stack_tokens::{token_scope, LocalKeyExt};
+
+thread_local! {
+    static FOO: u32 = 42;
+}
+
+fn main() {
+    let value: &u32 = token_scope! {
+        scope;
+        FOO.borrow(scope)
+    };
+    assert_eq!(*value, 42);
+}
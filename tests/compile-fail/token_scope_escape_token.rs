@@ -0,0 +1,13 @@
+use stack_tokens::{token_scope, LocalKeyExt};
+
+thread_local! {
+    static FOO: u32 = 42;
+}
+
+fn main() {
+    let token = token_scope! {
+        scope;
+        scope
+    };
+    assert_eq!(*FOO.borrow(token), 42);
+}
@@ -0,0 +1,11 @@
+/// Compile tests for the lifetime guarantees of the crate.
+///
+/// The cases in `tests/compile-fail` must be rejected by the compiler with
+/// the error recorded next to them, the cases in `tests/pass` must compile.
+/// Run with `TRYBUILD=overwrite` to update the recorded errors.
+#[test]
+fn compile_tests() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/compile-fail/*.rs");
+    t.pass("tests/pass/*.rs");
+}
@@ -0,0 +1,14 @@
+use stack_tokens::{stack_token, LocalKeyExt};
+
+thread_local! {
+    static FOO: u32 = 42;
+}
+
+fn main() {
+    stack_token!(scope);
+    let value = FOO.borrow(scope);
+
+    // shadowing the binding does not end the token early
+    stack_token!(scope);
+    assert_eq!(*value, *FOO.borrow(scope));
+}
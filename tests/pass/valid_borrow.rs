@@ -0,0 +1,18 @@
+use stack_tokens::{stack_token, LocalKeyExt, RefCellLocalKeyExt};
+use std::cell::RefCell;
+
+thread_local! {
+    static FOO: u32 = 42;
+    static BAR: RefCell<Vec<u32>> = RefCell::default();
+}
+
+fn get<'stack>(token: &'stack stack_tokens::StackToken) -> &'stack u32 {
+    FOO.borrow(token)
+}
+
+fn main() {
+    stack_token!(scope);
+    let value = get(scope);
+    BAR.as_mut(scope).push(*value);
+    assert_eq!(*BAR.as_ref(scope), vec![42]);
+}